@@ -0,0 +1,223 @@
+use arborist_git::{BranchAction, Error, KeyBindings, Result, SortKey};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use toml::Spanned;
+
+/// Preferences read from `~/.config/arborist/config.toml`. Every field mirrors a command line
+/// flag and is only a default, anything given on the command line wins.
+#[derive(Default)]
+pub struct Config {
+    pub dry_run: Option<bool>,
+    pub force: Option<bool>,
+    pub color: Option<bool>,
+    pub remotes: Option<bool>,
+    pub protect: Option<Vec<String>>,
+    pub older_than: Option<i64>,
+    pub sort: Option<SortKey>,
+    pub reverse: Option<bool>,
+    pub limit: Option<usize>,
+    pub group: Option<bool>,
+    pub hash_len: Option<usize>,
+    pub auto_delete_merged: Option<bool>,
+    pub tui: Option<bool>,
+    pub deferred: Option<bool>,
+    pub relative_time: Option<bool>,
+    /// From the `[keys]` section, like `delete = "x"`.
+    pub keys: KeyBindings,
+}
+
+/// The file as written, before the settings that need more than TOML's types are checked.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    dry_run: Option<bool>,
+    force: Option<bool>,
+    color: Option<bool>,
+    remotes: Option<bool>,
+    protect: Option<Vec<String>>,
+    older_than: Option<i64>,
+    sort: Option<Spanned<String>>,
+    reverse: Option<bool>,
+    limit: Option<usize>,
+    group: Option<bool>,
+    hash_len: Option<usize>,
+    auto_delete_merged: Option<bool>,
+    tui: Option<bool>,
+    deferred: Option<bool>,
+    relative_time: Option<bool>,
+    keys: BTreeMap<Spanned<String>, Spanned<String>>,
+}
+
+impl Config {
+    /// Loads the config file, a missing file is the same as an empty one.
+    pub fn load() -> Result<Config> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => Config::parse(&contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Config> {
+        let invalid = |span: Range<usize>, message: String| Error::InvalidConfig {
+            line: contents[..span.start].matches('\n').count() + 1,
+            message,
+        };
+
+        let raw: RawConfig = toml::from_str(contents).map_err(|error| {
+            let span = error.span().unwrap_or(0..0);
+            invalid(span, error.message().to_string())
+        })?;
+
+        let sort = match raw.sort {
+            Some(sort) => Some(sort.get_ref().parse().map_err(|()| {
+                let message = "'sort' should be \"date\", \"name\" or \"unique\"".to_string();
+                invalid(sort.span(), message)
+            })?),
+            None => None,
+        };
+
+        let mut keys = KeyBindings::default();
+        // Where each key was bound, to point at the right line when two actions share a key.
+        let mut bound_keys = Vec::new();
+        for (name, key) in &raw.keys {
+            let action: BranchAction = name.get_ref().parse().map_err(|()| {
+                invalid(name.span(), format!("unknown action '{}'", name.get_ref()))
+            })?;
+            let mut chars = key.get_ref().chars();
+            let bound = match (chars.next(), chars.next()) {
+                (Some(bound), None) if bound != '?' => bound,
+                _ => {
+                    let message = format!(
+                        "'keys.{}' should be a single key other than '?'",
+                        name.get_ref()
+                    );
+                    return Err(invalid(key.span(), message));
+                }
+            };
+            keys.bind(action, bound);
+            bound_keys.push((key.span(), bound));
+        }
+
+        if let Some(action) = keys.conflict() {
+            let key = keys.key(action);
+            let span = bound_keys
+                .iter()
+                .rev()
+                .find(|(_, bound)| *bound == key)
+                .map_or(0..0, |(span, _)| span.clone());
+            return Err(invalid(
+                span,
+                format!("'{}' is bound to more than one action", key),
+            ));
+        }
+
+        Ok(Config {
+            dry_run: raw.dry_run,
+            force: raw.force,
+            color: raw.color,
+            remotes: raw.remotes,
+            protect: raw.protect,
+            older_than: raw.older_than,
+            sort,
+            reverse: raw.reverse,
+            limit: raw.limit,
+            group: raw.group,
+            hash_len: raw.hash_len,
+            auto_delete_merged: raw.auto_delete_merged,
+            tui: raw.tui,
+            deferred: raw.deferred,
+            relative_time: raw.relative_time,
+            keys,
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/arborist/config.toml`, falling back to `~/.config`.
+fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(base.join("arborist").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_supported_value() {
+        let config = Config::parse(
+            r#"
+            # Preferences
+            protect = ["main", 'develop'] # trailing comment
+            older_than = 30
+            color = false
+            sort = "name"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.protect,
+            Some(vec!["main".to_string(), "develop".to_string()])
+        );
+        assert_eq!(config.older_than, Some(30));
+        assert_eq!(config.color, Some(false));
+        assert!(matches!(config.sort, Some(SortKey::Name)));
+    }
+
+    #[test]
+    fn arrays_can_span_lines() {
+        let config = Config::parse("protect = [\n  \"main\",\n  \"release\",\n]\n").unwrap();
+
+        assert_eq!(
+            config.protect,
+            Some(vec!["main".to_string(), "release".to_string()])
+        );
+    }
+
+    #[test]
+    fn keys_can_be_rebound() {
+        let config = Config::parse("[keys]\ndelete = \"x\"\nkeep = \"d\"\n").unwrap();
+
+        assert_eq!(config.keys.action('x').unwrap(), BranchAction::Delete);
+        assert_eq!(config.keys.action('d').unwrap(), BranchAction::Keep);
+    }
+
+    #[test]
+    fn keys_cannot_be_shared() {
+        match Config::parse("[keys]\ndelete = \"k\"\n") {
+            Err(Error::InvalidConfig { line: 2, .. }) => {}
+            other => panic!("expected an error on line 2, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_settings() {
+        match Config::parse("sort = \"name\"\nlimit = \"ten\"\n") {
+            Err(Error::InvalidConfig { line: 2, .. }) => {}
+            other => panic!("expected an error on line 2, got {:?}", other.err()),
+        }
+        match Config::parse("limit = 3\nsort = \"size\"\n") {
+            Err(Error::InvalidConfig { line: 2, .. }) => {}
+            other => panic!("expected an error on line 2, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(Config::parse("colour = false\n").is_err());
+    }
+}
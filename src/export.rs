@@ -0,0 +1,211 @@
+use super::{truncate, Tone, MIN_NAME_WIDTH};
+use arborist_git::{Branch, Result, UpstreamStatus};
+use chrono::NaiveDateTime;
+use std::io::Write;
+
+/// What every format exports about a branch.
+struct Row {
+    name: String,
+    /// The full reference name, like `refs/heads/feature`.
+    refname: String,
+    id: String,
+    time: String,
+    /// The same moment in the committer's time zone, for people to read.
+    local_time: NaiveDateTime,
+    is_head: bool,
+    is_remote: bool,
+    ahead: usize,
+    behind: usize,
+    /// The remote-tracking branch it follows, like `origin/feature`.
+    upstream: Option<String>,
+}
+
+fn rows(branches: &[Branch]) -> Result<Vec<Row>> {
+    branches
+        .iter()
+        .map(|branch| {
+            let (ahead, behind) = match branch.upstream {
+                UpstreamStatus::Ahead(ahead) => (ahead, 0),
+                UpstreamStatus::Behind(behind) => (0, behind),
+                UpstreamStatus::Diverged { ahead, behind } => (ahead, behind),
+                UpstreamStatus::None | UpstreamStatus::Gone | UpstreamStatus::InSync => (0, 0),
+            };
+
+            Ok(Row {
+                name: branch.name.clone(),
+                refname: branch.refname(),
+                id: branch.id.to_string(),
+                time: branch.commit_time().to_rfc3339(),
+                local_time: branch.time,
+                is_head: branch.is_head,
+                is_remote: branch.is_remote,
+                ahead,
+                behind,
+                upstream: branch.upstream_name()?,
+            })
+        })
+        .collect()
+}
+
+/// Writes the branches as a JSON array, one object per line so the output diffs nicely.
+pub fn write_json(out: &mut impl Write, branches: &[Branch]) -> Result<()> {
+    let rows = rows(branches)?;
+    write!(out, "[")?;
+
+    for (index, row) in rows.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(
+            out,
+            "{}\n  {{\"name\": {}, \"id\": {}, \"time\": {}, \"is_head\": {}}}",
+            separator,
+            json_string(&row.name),
+            json_string(&row.id),
+            json_string(&row.time),
+            row.is_head
+        )?;
+    }
+
+    if !rows.is_empty() {
+        writeln!(out)?;
+    }
+    writeln!(out, "]")?;
+
+    Ok(())
+}
+
+/// Writes the branches as CSV with a header row, ids shortened to `hash_len` characters and
+/// an empty upstream for branches without one.
+pub fn write_csv(out: &mut impl Write, branches: &[Branch], hash_len: usize) -> Result<()> {
+    writeln!(out, "name,short_id,date,ahead,behind,upstream")?;
+
+    for row in rows(branches)? {
+        let short_id: String = row.id.chars().take(hash_len).collect();
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            csv_field(&row.name),
+            short_id,
+            row.time,
+            row.ahead,
+            row.behind,
+            csv_field(row.upstream.as_deref().unwrap_or_default())
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes the branches as a table with a header and columns padded to their widest value. With
+/// a `width` the branch names, and then whole lines, are cut to fit into it. With `full_ref`
+/// branches are named by their full reference name instead.
+pub fn write_table(
+    out: &mut impl Write,
+    branches: &[Branch],
+    width: Option<usize>,
+    color: bool,
+    full_ref: bool,
+) -> Result<()> {
+    let header = ["BRANCH", "LAST COMMIT", "AHEAD/BEHIND", "UPSTREAM"].map(String::from);
+    let rows = rows(branches)?;
+    let lines: Vec<[String; 4]> = rows
+        .iter()
+        .map(|row| {
+            let name = if full_ref { &row.refname } else { &row.name };
+            [
+                name.clone(),
+                row.local_time.format("%Y-%m-%d %H:%M").to_string(),
+                format!("+{} -{}", row.ahead, row.behind),
+                row.upstream.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let mut widths = [0; 4];
+    for line in Some(&header).into_iter().chain(&lines) {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // Two spaces between columns. The name column gives up room first since the start of a
+    // name is usually enough to tell which branch it is.
+    if let Some(width) = width {
+        let rest: usize = widths[1..].iter().map(|width| width + 2).sum();
+        if widths[0] + rest > width {
+            widths[0] = width
+                .saturating_sub(rest)
+                .max(MIN_NAME_WIDTH)
+                .min(widths[0]);
+        }
+    }
+
+    let paint = |text: String, tone: Tone| {
+        if color {
+            tone.style().apply(text).to_string()
+        } else {
+            text
+        }
+    };
+
+    for (index, line) in Some(&header).into_iter().chain(&lines).enumerate() {
+        let row = index.checked_sub(1).map(|index| &rows[index]);
+        let cells: Vec<String> = line
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", truncate(cell, width), width = width))
+            .collect();
+        let text = truncate(cells.join("  ").trim_end(), width.unwrap_or(usize::MAX));
+
+        // Styling goes on last so the escape codes don't count towards the padding.
+        let text = match row {
+            None => paint(text, Tone::Key),
+            Some(row) if row.is_remote => paint(text, Tone::Remote),
+            Some(row) if row.is_head => paint(text, Tone::Highlight),
+            Some(_) => text,
+        };
+        writeln!(out, "{}", text)?;
+    }
+
+    Ok(())
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes the field when it has a comma, quote or line break in it, doubling any quotes.
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("feature/x"), "feature/x");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,57 @@
+/// Matches `text` against a shell-style pattern where `*` stands for any run of characters,
+/// slashes included, and `?` for exactly one character.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` when the rest of the pattern stops matching.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Lets the `*` swallow one more character and tries again.
+                Some((star, star_t)) => {
+                    p = star + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("tmp/*", "tmp/experiment"));
+        assert!(matches("tmp/*", "tmp/nested/experiment"));
+        assert!(matches("*-old", "feature-old"));
+        assert!(matches("fix-?", "fix-1"));
+        assert!(matches("*a*b*", "xaybz"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn mismatches() {
+        assert!(!matches("tmp/*", "feature/tmp"));
+        assert!(!matches("fix-?", "fix-10"));
+        assert!(!matches("main", "maint"));
+        assert!(!matches("*a*b", "xaybz"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{Branch, DeletedBranch, Result};
+use git2::{Oid, Repository};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// The branches the user already chose to keep along with their tips, stored in
+/// `.git/arborist-keep` so later runs don't ask about them again until the branch gets new
+/// commits.
+pub struct KeepFile {
+    path: PathBuf,
+    kept: BTreeSet<(String, Oid)>,
+}
+
+impl KeepFile {
+    pub fn open(repo: &Repository) -> Result<KeepFile> {
+        let path = repo.path().join("arborist-keep");
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        // Each line is `<oid> <branch name>`, branch names can't contain spaces.
+        let kept = contents
+            .lines()
+            .filter_map(|line| line.trim().split_once(' '))
+            .filter_map(|(oid, name)| Some((name.to_string(), Oid::from_str(oid).ok()?)))
+            .collect();
+
+        Ok(KeepFile { path, kept })
+    }
+
+    /// The names of the kept branches with the commit each one was kept at.
+    pub fn kept(&self) -> &BTreeSet<(String, Oid)> {
+        &self.kept
+    }
+
+    pub fn record(&mut self, branch: &Branch) -> Result<()> {
+        if !self.kept.insert((branch.name.clone(), branch.id)) {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{} {}", branch.id, branch.name)?;
+
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        self.kept.clear();
+
+        match fs::remove_file(&self.path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Branch names the user asked to keep forever, stored in `.git/arborist-protect` one per line.
+/// Unlike `KeepFile` this doesn't care what the branch points at.
+pub struct ProtectFile {
+    path: PathBuf,
+    names: BTreeSet<String>,
+}
+
+impl ProtectFile {
+    pub fn open(repo: &Repository) -> Result<ProtectFile> {
+        let path = repo.path().join("arborist-protect");
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let names = contents
+            .lines()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+
+        Ok(ProtectFile { path, names })
+    }
+
+    pub fn names(&self) -> &BTreeSet<String> {
+        &self.names
+    }
+
+    pub fn record(&mut self, branch: &Branch) -> Result<()> {
+        if !self.names.insert(branch.name.clone()) {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", branch.name)?;
+
+        Ok(())
+    }
+}
+
+/// Notes attached to branches the user kept, stored in `.git/arborist-notes` as lines of
+/// `<oid> <note>`. A later line for the same commit replaces the note and one without any text
+/// removes it, so the file only ever grows by appending like `KeepFile`.
+pub struct NoteFile {
+    path: PathBuf,
+    notes: HashMap<Oid, String>,
+}
+
+impl NoteFile {
+    pub fn open(repo: &Repository) -> Result<NoteFile> {
+        let path = repo.path().join("arborist-notes");
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut notes = HashMap::new();
+        for line in contents.lines() {
+            let (oid, note) = line.split_once(' ').unwrap_or((line, ""));
+            let oid = match Oid::from_str(oid.trim()) {
+                Ok(oid) => oid,
+                Err(_) => continue,
+            };
+
+            match note.trim() {
+                "" => notes.remove(&oid),
+                note => notes.insert(oid, note.to_string()),
+            };
+        }
+
+        Ok(NoteFile { path, notes })
+    }
+
+    /// The note for the commit the branch points at.
+    pub fn get(&self, branch: &Branch) -> Option<&str> {
+        self.notes.get(&branch.id).map(String::as_str)
+    }
+
+    /// Attaches `note` to the commit the branch points at, an empty note removes it.
+    pub fn record(&mut self, branch: &Branch, note: &str) -> Result<()> {
+        let note = note.trim();
+        match note {
+            "" => self.notes.remove(&branch.id),
+            note => self.notes.insert(branch.id, note.to_string()),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{} {}", branch.id, note)?;
+
+        Ok(())
+    }
+}
+
+/// The branches deleted in the latest session that deleted any, stored in
+/// `.git/arborist-last-session` as `<oid> <name>` lines with ` remote` after remote-tracking
+/// branches. The file is rewritten with every deletion so it survives a session that crashes.
+pub struct SessionFile {
+    path: PathBuf,
+    deleted: Vec<DeletedBranch>,
+}
+
+impl SessionFile {
+    /// Starts a new session, the previous one's file is only replaced once something is deleted.
+    pub fn new(repo: &Repository) -> SessionFile {
+        SessionFile {
+            path: repo.path().join("arborist-last-session"),
+            deleted: Vec::new(),
+        }
+    }
+
+    /// The branches the last session deleted, in the order it deleted them.
+    pub fn load(repo: &Repository) -> Result<Vec<DeletedBranch>> {
+        let path = SessionFile::new(repo).path;
+
+        // Names are kept as git has them, which isn't always valid UTF-8. They can't contain
+        // spaces or line breaks though.
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let deleted = contents
+            .split(|&byte| byte == b'\n')
+            .filter_map(|line| {
+                let mut fields = line.split(|&byte| byte == b' ');
+                let id = Oid::from_str(std::str::from_utf8(fields.next()?).ok()?).ok()?;
+                let raw_name = fields.next().filter(|name| !name.is_empty())?.to_vec();
+                let is_remote = fields.next() == Some(&b"remote"[..]);
+                Some(DeletedBranch {
+                    name: String::from_utf8_lossy(&raw_name).into_owned(),
+                    raw_name,
+                    id,
+                    is_remote,
+                })
+            })
+            .collect();
+
+        Ok(deleted)
+    }
+
+    pub fn record(&mut self, deleted: &DeletedBranch) -> Result<()> {
+        self.deleted.push(deleted.clone());
+        self.write()
+    }
+
+    /// Takes a branch out again after its deletion was undone.
+    pub fn forget(&mut self, restored: &DeletedBranch) -> Result<()> {
+        self.deleted.retain(|deleted| {
+            deleted.raw_name != restored.raw_name || deleted.is_remote != restored.is_remote
+        });
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        let mut contents = Vec::new();
+        for deleted in &self.deleted {
+            let kind = if deleted.is_remote { " remote" } else { "" };
+            contents.extend_from_slice(format!("{} ", deleted.id).as_bytes());
+            contents.extend_from_slice(&deleted.raw_name);
+            contents.extend_from_slice(format!("{}\n", kind).as_bytes());
+        }
+
+        fs::write(&self.path, contents)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,2163 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use git2::build::CheckoutBuilder;
+use git2::{
+    BranchType, Commit, Cred, CredentialType, DiffFormat, ErrorClass, ErrorCode, Oid, PushOptions,
+    RemoteCallbacks, Repository, StatusOptions,
+};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::string::FromUtf8Error;
+
+pub mod glob;
+mod keep;
+
+pub use keep::{KeepFile, NoteFile, ProtectFile, SessionFile};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Branches that are never offered for deletion unless `--protect` is given.
+pub const DEFAULT_PROTECTED: &[&str] = &["master", "main"];
+
+/// Decides which branches `get_branches` returns.
+#[derive(Default)]
+pub struct ScanOptions {
+    /// Only keep branches merged into this commit.
+    pub merged_into: Option<Oid>,
+    /// Leave out branches merged into this commit, the opposite of `merged_into`. With HEAD
+    /// this drops the branches already part of the current line of work.
+    pub not_merged_into: Option<Oid>,
+    /// Only keep branches whose last commit is at least this many days old.
+    pub older_than: Option<i64>,
+    /// Only keep branches whose last commit was made at or after this moment, in UTC.
+    pub since: Option<NaiveDateTime>,
+    /// Only keep branches whose last commit was made at or before this moment, in UTC.
+    pub until: Option<NaiveDateTime>,
+    /// Branch names that are never returned, for remote-tracking branches this is matched
+    /// against the name without the remote. These are `glob` patterns too, so `release/*`
+    /// protects every release branch while a plain name only matches itself.
+    pub protected: BTreeSet<String>,
+    /// Branches whose names match any of these `glob` patterns are never returned, matched
+    /// without the remote just like `protected`.
+    pub exclude: Vec<String>,
+    /// When not empty, only branches matching one of these `glob` patterns are returned, matched
+    /// the same way as `exclude` which wins over it.
+    pub include: Vec<String>,
+    /// Also return remote-tracking branches.
+    pub remotes: bool,
+    /// Only return local branches without any upstream configured, the ones that were most
+    /// likely never pushed. A branch whose upstream is gone has one configured.
+    pub local_only: bool,
+    /// Branches with one of these names whose tip is still the commit next to it are not
+    /// returned.
+    pub kept: BTreeSet<(String, Oid)>,
+    /// The order branches are returned in.
+    pub sort: SortKey,
+    /// What `SortKey::Unique` counts commits against, HEAD when `None`.
+    pub base: Option<Oid>,
+    /// Counts commits ahead of and behind the upstream, and unique ones for `SortKey::Unique`,
+    /// along first parents only like `git log --first-parent` does.
+    pub first_parent: bool,
+    /// Flips `sort` around.
+    pub reverse: bool,
+    /// Only return this many branches after sorting.
+    pub limit: Option<usize>,
+    /// Clusters branches by `Branch::group`, groups in alphabetical order and ungrouped
+    /// branches last, keeping the `sort` order within each group.
+    pub group: bool,
+    /// Returns branches whose names aren't valid UTF-8 with the invalid bytes replaced by
+    /// U+FFFD, instead of leaving them out and listing them in `Scan::invalid_names`.
+    pub lossy_names: bool,
+    /// Only looks up this branch instead of scanning every branch. The other filters still
+    /// apply, except that a kept branch is returned anyway.
+    pub only: Option<String>,
+}
+
+/// The outcome of `get_branches`.
+pub struct Scan<'repo> {
+    pub branches: Vec<Branch<'repo>>,
+    /// How many branches there are before any filter, including the ones left out.
+    pub found: usize,
+    /// How many branches passed every filter but were cut off by `ScanOptions::limit`.
+    pub omitted: usize,
+    /// Branches that were left out because their names aren't valid UTF-8, shown lossily.
+    pub invalid_names: Vec<String>,
+}
+
+/// What `get_branches` orders the branches by.
+#[derive(Clone, Copy, Default)]
+pub enum SortKey {
+    /// Oldest last commit first, by name among equals.
+    #[default]
+    Date,
+    /// Alphabetically, ignoring case.
+    Name,
+    /// Fewest commits that aren't on `ScanOptions::base` first, the safest to delete, with
+    /// the oldest first among equals.
+    Unique,
+}
+
+impl FromStr for SortKey {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "date" => Ok(SortKey::Date),
+            "name" => Ok(SortKey::Name),
+            "unique" => Ok(SortKey::Unique),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Lazily goes through the branches that pass a `ScanOptions`, in whatever order git lists
+/// them. This skips `sort`, `reverse`, `group` and `limit` since those need every branch first,
+/// use `get_branches` for them or when the whole list is needed anyway.
+pub struct Branches<'repo, 'options> {
+    repo: &'repo Repository,
+    options: &'options ScanOptions,
+    worktree_heads: BTreeSet<Vec<u8>>,
+    ignored: Vec<IgnorePattern>,
+    candidates: Box<dyn Iterator<Item = Result<(git2::Branch<'repo>, BranchType)>> + 'repo>,
+    found: usize,
+    invalid_names: Vec<String>,
+}
+
+impl<'repo, 'options> Branches<'repo, 'options> {
+    pub fn new(repo: &'repo Repository, options: &'options ScanOptions) -> Result<Self> {
+        let kind = if options.remotes {
+            None
+        } else {
+            Some(BranchType::Local)
+        };
+
+        let candidates: Box<dyn Iterator<Item = _>> = match &options.only {
+            Some(name) => Box::new(std::iter::once(Ok(find_branch(
+                repo,
+                name,
+                options.remotes,
+            )?))),
+            None => Box::new(
+                repo.branches(kind)?
+                    .map(|candidate| candidate.map_err(Error::from)),
+            ),
+        };
+
+        Ok(Branches {
+            repo,
+            options,
+            worktree_heads: worktree_heads(repo)?,
+            ignored: ignore_patterns(repo)?,
+            candidates,
+            found: 0,
+            invalid_names: Vec::new(),
+        })
+    }
+
+    /// How many branches were looked at so far, whether they were filtered out or not.
+    pub fn found(&self) -> usize {
+        self.found
+    }
+
+    /// The branches passed over so far because their names aren't valid UTF-8, shown lossily.
+    pub fn invalid_names(&self) -> &[String] {
+        &self.invalid_names
+    }
+
+    /// The candidate as a `Branch`, or `None` when one of the options filters it out.
+    fn check(
+        &mut self,
+        branch: git2::Branch<'repo>,
+        kind: BranchType,
+    ) -> Result<Option<Branch<'repo>>> {
+        let is_remote = kind == BranchType::Remote;
+
+        // Skips symbolic refs like `origin/HEAD`, they just point at another remote branch.
+        if branch.get().symbolic_target_bytes().is_some() {
+            log::trace!("skipping symbolic ref {:?}", branch.get().name());
+            return Ok(None);
+        }
+        self.found += 1;
+
+        let name = match String::from_utf8(branch.name_bytes()?.to_vec()) {
+            Ok(name) => name,
+            Err(error) => {
+                let name = String::from_utf8_lossy(error.as_bytes()).into_owned();
+                if !self.options.lossy_names {
+                    log::debug!("skipping '{}': name isn't valid UTF-8", name);
+                    self.invalid_names.push(name);
+                    return Ok(None);
+                }
+                name
+            }
+        };
+
+        let commit = branch.get().peel_to_commit()?;
+
+        // Naming a branch is a deliberate choice to look at it again.
+        let kept = (name.clone(), commit.id());
+        if self.options.only.is_none() && self.options.kept.contains(&kept) {
+            log::debug!("skipping '{}': kept at {}", name, commit.id());
+            return Ok(None);
+        }
+
+        let short_name = match name.split_once('/') {
+            Some((_, short_name)) if is_remote => short_name,
+            _ => &name,
+        };
+
+        let matches_any = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| glob::matches(pattern, short_name))
+        };
+        let protected = self
+            .options
+            .protected
+            .iter()
+            .any(|pattern| glob::matches(pattern, short_name));
+        if protected {
+            log::debug!("skipping '{}': protected", name);
+            return Ok(None);
+        }
+        if is_ignored(&self.ignored, short_name) {
+            log::debug!("skipping '{}': ignored by .arboristignore", name);
+            return Ok(None);
+        }
+        if matches_any(&self.options.exclude) {
+            log::debug!("skipping '{}': matches an excluded pattern", name);
+            return Ok(None);
+        }
+        if !self.options.include.is_empty() && !matches_any(&self.options.include) {
+            log::debug!("skipping '{}': matches no included pattern", name);
+            return Ok(None);
+        }
+
+        if let Some(base) = self.options.merged_into {
+            if !is_merged_into(self.repo, commit.id(), base)? {
+                log::debug!("skipping '{}': not merged into {}", name, base);
+                return Ok(None);
+            }
+        }
+        if let Some(head) = self.options.not_merged_into {
+            if is_merged_into(self.repo, commit.id(), head)? {
+                log::debug!("skipping '{}': already merged into {}", name, head);
+                return Ok(None);
+            }
+        }
+
+        let time = commit.time();
+        let offset = Duration::minutes(i64::from(time.offset_minutes()));
+        let time = NaiveDateTime::from_timestamp(time.seconds(), 0) + offset;
+
+        let utc_time = time - offset;
+        if self.options.since.is_some_and(|since| utc_time < since)
+            || self.options.until.is_some_and(|until| utc_time > until)
+        {
+            log::debug!("skipping '{}': last commit outside the date window", name);
+            return Ok(None);
+        }
+
+        if let Some(days) = self.options.older_than {
+            let now = Utc::now().naive_utc() + offset;
+            if now - time < Duration::days(days) {
+                log::debug!("skipping '{}': newer than {} days", name, days);
+                return Ok(None);
+            }
+        }
+
+        // Remote-tracking branches have no upstream of their own.
+        let upstream = if is_remote {
+            UpstreamStatus::None
+        } else {
+            upstream_status(self.repo, &branch, commit.id(), self.options.first_parent)?
+        };
+        if self.options.local_only && (is_remote || upstream != UpstreamStatus::None) {
+            log::debug!("skipping '{}': has an upstream", name);
+            return Ok(None);
+        }
+
+        log::debug!(
+            "found '{}' at {}, upstream {:?}",
+            name,
+            commit.id(),
+            upstream
+        );
+
+        let summary = String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default());
+        let author = String::from_utf8_lossy(commit.author().name_bytes()).into_owned();
+
+        Ok(Some(Branch {
+            id: commit.id(),
+            summary: summary.into_owned(),
+            author,
+            time,
+            offset: FixedOffset::east(offset.num_seconds() as i32),
+            name,
+            is_head: branch.is_head(),
+            is_remote,
+            in_worktree: !is_remote && self.worktree_heads.contains(branch.get().name_bytes()),
+            upstream,
+            branch,
+        }))
+    }
+}
+
+impl<'repo, 'options> Iterator for Branches<'repo, 'options> {
+    type Item = Result<Branch<'repo>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (branch, kind) = match self.candidates.next()? {
+                Ok(candidate) => candidate,
+                Err(error) => return Some(Err(error)),
+            };
+
+            match self.check(branch, kind) {
+                Ok(Some(branch)) => return Some(Ok(branch)),
+                Ok(None) => continue,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+/// Collects the branches that pass `options`, ordered by `options.sort`. Every branch is
+/// checked before this returns, see `Branches` to stop early.
+pub fn get_branches<'repo>(repo: &'repo Repository, options: &ScanOptions) -> Result<Scan<'repo>> {
+    let mut scan = Branches::new(repo, options)?;
+    let mut branches = scan.by_ref().collect::<Result<Vec<_>>>()?;
+    let found = scan.found;
+    let invalid_names = scan.invalid_names;
+
+    // Ties are broken by name so branches with the same commit time, like ones made off the
+    // same commit, come up in the same order every run.
+    let by_date = |a: &Branch, b: &Branch| a.time.cmp(&b.time).then_with(|| a.name.cmp(&b.name));
+    match options.sort {
+        SortKey::Date => branches.sort_by(by_date),
+        SortKey::Name => {
+            branches.sort_by_cached_key(|branch| (branch.name.to_lowercase(), branch.name.clone()))
+        }
+        SortKey::Unique => {
+            let base = match options.base {
+                Some(base) => Ok(base),
+                None => resolve_base(repo, None),
+            };
+
+            // Without a base, like before the first commit, there's nothing to count against
+            // and the date order is the next best thing.
+            match base.and_then(|base| BaseCommits::new(repo, base, options.first_parent)) {
+                Ok(base) => branches.sort_by_cached_key(|branch| {
+                    let unique = base.unique_commits(repo, branch.id).unwrap_or(usize::MAX);
+                    (unique, branch.time, branch.name.clone())
+                }),
+                Err(error) => {
+                    log::debug!("can't count unique commits, sorting by date: {}", error);
+                    branches.sort_by(by_date);
+                }
+            }
+        }
+    }
+
+    if options.reverse {
+        branches.reverse();
+    }
+
+    if options.group {
+        branches.sort_by(|a, b| match (a.group(), b.group()) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+    }
+
+    let mut omitted = 0;
+    if let Some(limit) = options.limit {
+        omitted = branches.len().saturating_sub(limit);
+        branches.truncate(limit);
+    }
+
+    Ok(Scan {
+        branches,
+        found,
+        omitted,
+        invalid_names,
+    })
+}
+
+/// The full ref names, like `refs/heads/feature`, checked out in linked worktrees. Worktrees
+/// that can't be opened, for example because their directory was removed, are left out.
+fn worktree_heads(repo: &Repository) -> Result<BTreeSet<Vec<u8>>> {
+    let mut heads = BTreeSet::new();
+
+    for name in repo.worktrees()?.iter().flatten() {
+        let head = repo
+            .find_worktree(name)
+            .and_then(|worktree| Repository::open_from_worktree(&worktree))
+            .and_then(|worktree_repo| {
+                let head = worktree_repo.find_reference("HEAD")?;
+                Ok(head.symbolic_target_bytes().map(<[u8]>::to_vec))
+            });
+
+        if let Ok(Some(head)) = head {
+            heads.insert(head);
+        }
+    }
+
+    Ok(heads)
+}
+
+/// A line of `.arboristignore`, `negated` for `!` lines that take branches back out of what
+/// earlier lines ignore.
+struct IgnorePattern {
+    pattern: String,
+    negated: bool,
+}
+
+/// Reads `.arboristignore` at the root of the working tree, one `glob` pattern per line like
+/// `.gitignore` with blank lines and `#` comments skipped. A leading backslash escapes a `!` or
+/// `#` that's part of the name. Bare repositories and a missing file ignore nothing.
+fn ignore_patterns(repo: &Repository) -> Result<Vec<IgnorePattern>> {
+    let path = match repo.workdir() {
+        Some(workdir) => workdir.join(".arboristignore"),
+        None => return Ok(Vec::new()),
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+
+    let patterns = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.strip_prefix('!') {
+            Some(pattern) => IgnorePattern {
+                pattern: pattern.to_string(),
+                negated: true,
+            },
+            None => IgnorePattern {
+                pattern: line.strip_prefix('\\').unwrap_or(line).to_string(),
+                negated: false,
+            },
+        })
+        .collect();
+    Ok(patterns)
+}
+
+/// Whether `name` is ignored, which the last line matching it decides just like in `.gitignore`.
+fn is_ignored(patterns: &[IgnorePattern], name: &str) -> bool {
+    patterns
+        .iter()
+        .rev()
+        .find(|ignore| glob::matches(&ignore.pattern, name))
+        .is_some_and(|ignore| !ignore.negated)
+}
+
+/// Looks up a local branch by name, falling back to remote-tracking branches with `remotes`.
+fn find_branch<'repo>(
+    repo: &'repo Repository,
+    name: &str,
+    remotes: bool,
+) -> Result<(git2::Branch<'repo>, BranchType)> {
+    let mut kinds = vec![BranchType::Local];
+    if remotes {
+        kinds.push(BranchType::Remote);
+    }
+
+    for kind in kinds {
+        match repo.find_branch(name, kind) {
+            Ok(branch) => return Ok((branch, kind)),
+            Err(error) if error.code() == ErrorCode::NotFound => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Err(Error::BranchNotFound(name.to_string()))
+}
+
+/// When the branch split off from `base`, the time of the commit the two have in common. That
+/// is `None` when they share no history at all.
+pub fn created_time(
+    repo: &Repository,
+    tip: Oid,
+    base: Oid,
+) -> Result<Option<DateTime<FixedOffset>>> {
+    let merge_base = match repo.merge_base(tip, base) {
+        Ok(merge_base) => merge_base,
+        Err(error) if error.code() == ErrorCode::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    let time = repo.find_commit(merge_base)?.time();
+    let offset = FixedOffset::east(time.offset_minutes() * 60);
+    Ok(Some(offset.timestamp(time.seconds(), 0)))
+}
+
+/// How a branch's history compares to another branch's, see `divergence`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divergence {
+    /// Commits only on the branch.
+    pub ahead: usize,
+    /// Commits only on the other branch.
+    pub behind: usize,
+    /// The newest commit both have, `None` when they share no history.
+    pub merge_base: Option<Oid>,
+}
+
+/// How `tip` relates to the branch named `other`, which is looked up as a local branch first
+/// and as a remote-tracking one after that.
+pub fn divergence(
+    repo: &Repository,
+    tip: Oid,
+    other: &str,
+    first_parent: bool,
+) -> Result<Divergence> {
+    let (other, _) = find_branch(repo, other, true)?;
+    let other = other.get().peel_to_commit()?.id();
+
+    let (ahead, behind) = ahead_behind(repo, tip, other, first_parent)?;
+    let merge_base = match repo.merge_base(tip, other) {
+        Ok(merge_base) => Some(merge_base),
+        Err(error) if error.code() == ErrorCode::NotFound => None,
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok(Divergence {
+        ahead,
+        behind,
+        merge_base,
+    })
+}
+
+/// How many commits `tip` has that `other` doesn't and the other way around. With
+/// `first_parent` only the first parent of every commit is followed from either side, so the
+/// commits a merge brought in don't count, while `other` still hides all of its history.
+pub fn ahead_behind(
+    repo: &Repository,
+    tip: Oid,
+    other: Oid,
+    first_parent: bool,
+) -> Result<(usize, usize)> {
+    if !first_parent {
+        return Ok(repo.graph_ahead_behind(tip, other)?);
+    }
+
+    let count = |from: Oid, hidden: Oid| -> Result<usize> {
+        let mut walk = repo.revwalk()?;
+        walk.simplify_first_parent()?;
+        walk.push(from)?;
+        walk.hide(hidden)?;
+
+        let mut count = 0;
+        for id in walk {
+            id?;
+            count += 1;
+        }
+        Ok(count)
+    };
+
+    Ok((count(tip, other)?, count(other, tip)?))
+}
+
+/// Whether tracked files have staged or unstaged changes, untracked files don't count since
+/// a checkout leaves them alone.
+pub fn has_uncommitted_changes(repo: &Repository) -> Result<bool> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    Ok(!repo.statuses(Some(&mut options))?.is_empty())
+}
+
+/// A branch counts as merged when its tip is the base commit itself or one of its ancestors.
+pub fn is_merged_into(repo: &Repository, tip: Oid, base: Oid) -> Result<bool> {
+    Ok(tip == base || repo.graph_descendant_of(base, tip)?)
+}
+
+/// Whether every commit on the branch can also be reached from a remote-tracking branch,
+/// checking the branch's upstream before falling back to every other remote.
+pub fn is_pushed(repo: &Repository, branch: &Branch) -> Result<bool> {
+    if branch.is_remote {
+        return Ok(true);
+    }
+
+    let contains_tip = |remote: &git2::Branch| -> Result<bool> {
+        match remote.get().target() {
+            Some(remote_tip) => is_merged_into(repo, branch.id, remote_tip),
+            None => Ok(false),
+        }
+    };
+
+    if let Ok(upstream) = branch.branch.upstream() {
+        if contains_tip(&upstream)? {
+            return Ok(true);
+        }
+    }
+
+    for remote in repo.branches(Some(BranchType::Remote))? {
+        let (remote, _) = remote?;
+        if contains_tip(&remote)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Works out how `branch` relates to the branch it tracks.
+fn upstream_status(
+    repo: &Repository,
+    branch: &git2::Branch,
+    tip: Oid,
+    first_parent: bool,
+) -> Result<UpstreamStatus> {
+    let upstream_name = match branch.get().name() {
+        Some(name) => match repo.branch_upstream_name(name) {
+            Ok(upstream_name) => upstream_name,
+            Err(error) if error.code() == ErrorCode::NotFound => return Ok(UpstreamStatus::None),
+            Err(error) => return Err(error.into()),
+        },
+        None => return Ok(UpstreamStatus::None),
+    };
+
+    // The upstream is still configured but its ref is missing, usually because the branch was
+    // deleted on the remote and pruned by a fetch.
+    let upstream_tip = match upstream_name.as_str().map(|name| repo.refname_to_id(name)) {
+        Some(Ok(upstream_tip)) => upstream_tip,
+        Some(Err(error)) if error.code() == ErrorCode::NotFound => return Ok(UpstreamStatus::Gone),
+        Some(Err(error)) => return Err(error.into()),
+        None => return Ok(UpstreamStatus::None),
+    };
+
+    Ok(match ahead_behind(repo, tip, upstream_tip, first_parent)? {
+        (0, 0) => UpstreamStatus::InSync,
+        (ahead, 0) => UpstreamStatus::Ahead(ahead),
+        (0, behind) => UpstreamStatus::Behind(behind),
+        (ahead, behind) => UpstreamStatus::Diverged { ahead, behind },
+    })
+}
+
+/// Whether some other ref, like another branch, a tag or a detached HEAD, still reaches the
+/// branch's tip. When none does, deleting the branch leaves its commits for git to garbage
+/// collect.
+pub fn is_reachable_elsewhere(repo: &Repository, branch: &Branch) -> Result<bool> {
+    let own_name = branch.branch.get().name_bytes();
+
+    for reference in repo.references()? {
+        let reference = reference?;
+        if reference.name_bytes() == own_name || reference.symbolic_target_bytes().is_some() {
+            continue;
+        }
+
+        // Refs that don't lead to a commit, like a tag of a tree, can't keep the branch alive.
+        let target = match reference.peel_to_commit() {
+            Ok(target) => target.id(),
+            Err(_) => continue,
+        };
+        if is_merged_into(repo, branch.id, target)? {
+            return Ok(true);
+        }
+    }
+
+    if repo.head_detached()? {
+        let head = repo.head()?.peel_to_commit()?.id();
+        return is_merged_into(repo, branch.id, head);
+    }
+
+    Ok(false)
+}
+
+/// The reviewed branches that can be stacked on each other, looked up for one branch at a time
+/// since comparing every pair up front gets slow with many branches. Branches already in the
+/// base don't count since deleting them leaves whatever was built on top of them with the base
+/// to rebase onto.
+pub struct Stacks {
+    tips: Vec<(String, Oid)>,
+}
+
+impl Stacks {
+    pub fn new(branches: &[Branch], base: &BaseCommits) -> Stacks {
+        let tips = branches
+            .iter()
+            .filter(|branch| !base.commits.contains(&branch.id))
+            .map(|branch| (branch.name.clone(), branch.id))
+            .collect();
+
+        Stacks { tips }
+    }
+
+    /// The names of the branches stacked on `branch`, the ones whose tips have its tip as an
+    /// ancestor.
+    pub fn above(&self, repo: &Repository, branch: &Branch) -> Result<Vec<String>> {
+        let mut above = Vec::new();
+        if !self.tips.iter().any(|(name, _)| *name == branch.name) {
+            return Ok(above);
+        }
+
+        for (name, id) in &self.tips {
+            if *id != branch.id && repo.graph_descendant_of(*id, branch.id)? {
+                above.push(name.clone());
+            }
+        }
+
+        Ok(above)
+    }
+
+    /// The names of the branches `branch` is stacked on.
+    pub fn below(&self, repo: &Repository, branch: &Branch) -> Result<Vec<String>> {
+        let mut below = Vec::new();
+        for (name, id) in &self.tips {
+            if *id != branch.id && repo.graph_descendant_of(branch.id, *id)? {
+                below.push(name.clone());
+            }
+        }
+
+        Ok(below)
+    }
+
+    /// A deleted branch is no longer part of any stack.
+    pub fn remove(&mut self, name: &str) {
+        self.tips.retain(|(tip, _)| tip != name);
+    }
+}
+
+/// Every commit reachable from a base, collected once so that the commits unique to each
+/// branch can be counted without walking the base's history again.
+pub struct BaseCommits {
+    commits: HashSet<Oid>,
+    first_parent: bool,
+}
+
+impl BaseCommits {
+    /// With `first_parent` the commits unique to a branch are only counted along the first
+    /// parents of its tip, the base itself still holds its whole history.
+    pub fn new(repo: &Repository, base: Oid, first_parent: bool) -> Result<BaseCommits> {
+        let mut walk = repo.revwalk()?;
+        walk.push(base)?;
+        let commits = walk.collect::<Result<_, _>>()?;
+
+        Ok(BaseCommits {
+            commits,
+            first_parent,
+        })
+    }
+
+    /// How many commits reachable from `tip` aren't reachable from the base, which is what
+    /// becomes unreachable when a branch at `tip` is deleted and nothing else points there.
+    pub fn unique_commits(&self, repo: &Repository, tip: Oid) -> Result<usize> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![tip];
+
+        while let Some(id) = pending.pop() {
+            if self.commits.contains(&id) || !seen.insert(id) {
+                continue;
+            }
+            let commit = repo.find_commit(id)?;
+            if self.first_parent {
+                pending.extend(commit.parent_id(0).ok());
+            } else {
+                pending.extend(commit.parent_ids());
+            }
+        }
+
+        Ok(seen.len())
+    }
+}
+
+/// How many commits reachable from `tips` can't be reached from any reference or a detached
+/// HEAD anymore, which is what `git gc` prunes once the reflogs no longer mention them.
+pub fn unreachable_commits(repo: &Repository, tips: &[Oid]) -> Result<usize> {
+    let mut walk = repo.revwalk()?;
+    for &tip in tips {
+        walk.push(tip)?;
+    }
+
+    for reference in repo.references()? {
+        // Tags can point at trees and blobs, only what leads to a commit matters here.
+        if let Ok(commit) = reference?.peel_to_commit() {
+            walk.hide(commit.id())?;
+        }
+    }
+    if repo.head_detached()? {
+        walk.hide_head()?;
+    }
+
+    let mut count = 0;
+    for id in walk {
+        id?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The commit and its changes against its first parent, laid out like `git show`.
+pub fn show_commit(repo: &Repository, id: Oid) -> Result<String> {
+    let commit = repo.find_commit(id)?;
+    let author = commit.author();
+    let time = commit.time();
+    let offset = FixedOffset::east(time.offset_minutes() * 60);
+    let date = offset.timestamp(time.seconds(), 0);
+
+    let mut text = format!(
+        "commit {}\nAuthor: {} <{}>\nDate:   {}\n\n",
+        id,
+        String::from_utf8_lossy(author.name_bytes()),
+        String::from_utf8_lossy(author.email_bytes()),
+        date.to_rfc2822()
+    );
+    for line in String::from_utf8_lossy(commit.message_bytes()).lines() {
+        text.push_str("    ");
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.push('\n');
+
+    // A root commit is shown against an empty tree, so everything in it is an addition.
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if let '+' | '-' | ' ' = line.origin() {
+            text.push(line.origin());
+        }
+        text.push_str(&String::from_utf8_lossy(line.content()));
+        true
+    })?;
+
+    Ok(text)
+}
+
+/// The last `limit` commits reachable from the branch, newest first.
+pub fn recent_commits<'repo>(
+    repo: &'repo Repository,
+    branch: &Branch,
+    limit: usize,
+) -> Result<Vec<Commit<'repo>>> {
+    let mut walk = repo.revwalk()?;
+    walk.push(branch.id)?;
+
+    walk.take(limit)
+        .map(|id| Ok(repo.find_commit(id?)?))
+        .collect()
+}
+
+/// Deletes `refname` on `remote`, as found by `Branch::upstream_ref`.
+pub fn delete_remote_branch(repo: &Repository, remote: &str, refname: &str) -> Result<()> {
+    let name = format!(
+        "{}/{}",
+        remote,
+        refname.strip_prefix("refs/heads/").unwrap_or(refname)
+    );
+    push_refspec(repo, remote, &format!(":{}", refname), &name)
+}
+
+/// Pushes a single refspec, authenticating through an SSH agent or git's credential helpers.
+/// `name` is what a rejection is reported for.
+fn push_refspec(repo: &Repository, remote: &str, refspec: &str, name: &str) -> Result<()> {
+    let config = repo.config()?;
+    let mut rejection = None;
+
+    {
+        let mut attempts = 0;
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(|url, username, allowed| {
+            // libgit2 keeps asking for as long as the credentials given are refused.
+            attempts += 1;
+            if attempts > 3 {
+                return Err(git2::Error::from_str("authentication failed"));
+            }
+
+            if allowed.contains(CredentialType::SSH_KEY) {
+                if let Some(username) = username {
+                    return Cred::ssh_key_from_agent(username);
+                }
+            }
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                return Cred::credential_helper(&config, url, username);
+            }
+            Cred::default()
+        });
+        callbacks.push_update_reference(|_, status| {
+            rejection = status.map(String::from);
+            Ok(())
+        });
+
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        repo.find_remote(remote)?
+            .push(&[refspec], Some(&mut options))?;
+    }
+
+    match rejection {
+        Some(reason) => Err(Error::PushRejected {
+            name: name.to_string(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Resolves the commit to compare against, falling back to whatever HEAD points at.
+pub fn resolve_base(repo: &Repository, base: Option<&str>) -> Result<Oid> {
+    let commit = match base {
+        Some(base) => repo.revparse_single(base)?.peel_to_commit()?,
+        None => repo.head()?.peel_to_commit()?,
+    };
+
+    Ok(commit.id())
+}
+
+/// The commit HEAD points at, `None` while HEAD is unborn like before the first commit or
+/// right after `git checkout --orphan`.
+pub fn head_commit(repo: &Repository) -> Result<Option<Oid>> {
+    match repo.head() {
+        Ok(head) => Ok(Some(head.peel_to_commit()?.id())),
+        Err(error)
+            if error.code() == ErrorCode::UnbornBranch || error.code() == ErrorCode::NotFound =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// A local branch along with the details of the commit it points at.
+pub struct Branch<'repo> {
+    pub id: Oid,
+    /// The first line of the last commit's message.
+    pub summary: String,
+    /// Who wrote the last commit.
+    pub author: String,
+    pub time: NaiveDateTime,
+    pub name: String,
+    pub is_head: bool,
+    pub is_remote: bool,
+    /// Checked out in a linked worktree, git refuses to delete it just like the HEAD branch.
+    pub in_worktree: bool,
+    pub upstream: UpstreamStatus,
+    offset: FixedOffset,
+    branch: git2::Branch<'repo>,
+}
+
+impl<'repo> Branch<'repo> {
+    /// The full name of the branch's reference, like `refs/heads/feature` or
+    /// `refs/remotes/origin/feature`, with invalid UTF-8 shown lossily.
+    pub fn refname(&self) -> String {
+        String::from_utf8_lossy(self.branch.get().name_bytes()).into_owned()
+    }
+
+    /// The last commit time including the committer's timezone, `time` is the same moment as
+    /// seen on their clock.
+    pub fn commit_time(&self) -> DateTime<FixedOffset> {
+        DateTime::from_utc(
+            self.time - Duration::seconds(self.offset.local_minus_utc().into()),
+            self.offset,
+        )
+    }
+
+    /// The name of the remote the branch's upstream lives on, `None` without an upstream.
+    pub fn upstream_remote(&self, repo: &Repository) -> Result<Option<String>> {
+        let name = match self.branch.get().name() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        match repo.branch_upstream_remote(name) {
+            Ok(remote) => Ok(remote.as_str().map(String::from)),
+            Err(error) if error.code() == ErrorCode::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// The remote-tracking branch the branch follows, like `origin/feature`. That's `None`
+    /// without an upstream, including when it was deleted since.
+    pub fn upstream_name(&self) -> Result<Option<String>> {
+        if self.is_remote {
+            return Ok(None);
+        }
+
+        match self.branch.upstream() {
+            Ok(upstream) => Ok(Some(
+                String::from_utf8_lossy(upstream.name_bytes()?).into_owned(),
+            )),
+            Err(error) if error.code() == ErrorCode::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Pushes the branch to its upstream when that's on `remote`, whatever its name is there.
+    /// Otherwise it's pushed to the same name on `remote`, which becomes the upstream when there
+    /// wasn't one yet.
+    pub fn push(&mut self, repo: &Repository, remote: &str) -> Result<()> {
+        let refname = format!("refs/heads/{}", self.name);
+        let target = match self.upstream_remote(repo)? {
+            Some(upstream_remote) if upstream_remote == remote => self.merge_ref(repo)?,
+            _ => None,
+        };
+        let refspec = format!("{}:{}", refname, target.as_deref().unwrap_or(&refname));
+        push_refspec(repo, remote, &refspec, &self.name)?;
+
+        if self.upstream == UpstreamStatus::None {
+            self.branch
+                .set_upstream(Some(&format!("{}/{}", remote, self.name)))?;
+        }
+        // Right after a push both sides are at the same commit, however history is counted.
+        self.upstream = upstream_status(repo, &self.branch, self.id, false)?;
+
+        Ok(())
+    }
+
+    /// The remote the upstream lives on and its ref name over there, like `origin` and
+    /// `refs/heads/feature`. `None` without an upstream or when it's gone already.
+    pub fn upstream_ref(&self, repo: &Repository) -> Result<Option<(String, String)>> {
+        if self.is_remote || self.upstream == UpstreamStatus::Gone {
+            return Ok(None);
+        }
+
+        let remote = match self.upstream_remote(repo)? {
+            Some(remote) => remote,
+            None => return Ok(None),
+        };
+
+        Ok(self.merge_ref(repo)?.map(|merge| (remote, merge)))
+    }
+
+    /// The upstream's ref name on its remote from `branch.<name>.merge`, like
+    /// `refs/heads/feature`.
+    fn merge_ref(&self, repo: &Repository) -> Result<Option<String>> {
+        match repo
+            .config()?
+            .get_string(&format!("branch.{}.merge", self.name))
+        {
+            Ok(merge) => Ok(Some(merge)),
+            Err(error) if error.code() == ErrorCode::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Tags the branch's tip as `archive/<name>` so it's kept after the branch is gone, returning
+    /// the tag's name. When that tag is taken by another commit a number is added, like
+    /// `archive/<name>-2`.
+    pub fn archive(&self, repo: &Repository) -> Result<String> {
+        let commit = repo.find_commit(self.id)?;
+
+        for attempt in 1.. {
+            let tag = match attempt {
+                1 => format!("archive/{}", self.name),
+                _ => format!("archive/{}-{}", self.name, attempt),
+            };
+
+            match repo.refname_to_id(&format!("refs/tags/{}", tag)) {
+                Ok(id) if id == self.id => return Ok(tag),
+                Ok(_) => continue,
+                Err(error) if error.code() == ErrorCode::NotFound => {
+                    repo.tag_lightweight(&tag, commit.as_object(), false)?;
+                    return Ok(tag);
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        unreachable!("there's always another number to try")
+    }
+
+    /// Checks the branch out into the working tree and points HEAD at it, like `git switch`.
+    /// Files with changes that would be overwritten make it fail instead.
+    pub fn checkout(&self, repo: &Repository) -> Result<()> {
+        let commit = repo.find_commit(self.id)?;
+        repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+
+        // HEAD can only be pointed at names git2 takes as strings.
+        let refname = String::from_utf8(self.branch.get().name_bytes().to_vec())?;
+        repo.set_head(&refname)?;
+        Ok(())
+    }
+
+    /// Deletes the branch, for remote-tracking branches only the local ref is removed. The
+    /// returned value has everything needed to bring the branch back.
+    pub fn delete(&mut self) -> Result<DeletedBranch> {
+        self.branch.delete().map_err(|source| Error::DeleteFailed {
+            name: self.name.clone(),
+            source,
+        })?;
+        Ok(self.deleted())
+    }
+
+    /// The git command that would recreate the branch once it's deleted.
+    pub fn undo_command(&self) -> String {
+        self.deleted().undo_command()
+    }
+
+    fn deleted(&self) -> DeletedBranch {
+        DeletedBranch {
+            name: self.name.clone(),
+            raw_name: self
+                .branch
+                .name_bytes()
+                .map_or_else(|_| self.name.clone().into_bytes(), <[u8]>::to_vec),
+            id: self.id,
+            is_remote: self.is_remote,
+        }
+    }
+
+    /// Everything before the first slash, like `feature` for `feature/login`. Remote-tracking
+    /// branches are grouped without their remote, branches without a slash have no group.
+    pub fn group(&self) -> Option<&str> {
+        let name = match self.name.split_once('/') {
+            Some((_, name)) if self.is_remote => name,
+            _ => &self.name,
+        };
+
+        name.split_once('/').map(|(group, _)| group)
+    }
+
+    /// Renames the branch, refusing names that clash with another branch's path like
+    /// `trash/feature` next to a branch named `trash`. Should the rename fail anyway the branch
+    /// is put back where it was.
+    pub fn rename(&mut self, repo: &Repository, new_name: &str) -> Result<()> {
+        let refname = self.refname();
+        if let Some(existing) = conflicting_branch(repo, new_name, &refname)? {
+            return Err(Error::NameConflict {
+                name: new_name.to_string(),
+                existing,
+            });
+        }
+
+        match self.branch.rename(new_name, false) {
+            Ok(branch) => self.branch = branch,
+            Err(error) => {
+                // libgit2 removes the old ref before writing the new one, a failure in between
+                // leaves neither of them.
+                if repo.find_reference(&refname).is_err() {
+                    self.deleted().restore(repo)?;
+                }
+                return Err(error.into());
+            }
+        }
+        self.name = new_name.to_string();
+        Ok(())
+    }
+}
+
+/// The branch that keeps a branch named `name` from existing, one whose name is a directory
+/// of `name` or the other way around. The branch at `own_ref` is left out, it's the one being
+/// renamed and makes way.
+fn conflicting_branch(repo: &Repository, name: &str, own_ref: &str) -> Result<Option<String>> {
+    for (end, _) in name.match_indices('/') {
+        let parent = format!("refs/heads/{}", &name[..end]);
+        if parent != own_ref && repo.find_reference(&parent).is_ok() {
+            return Ok(Some(name[..end].to_string()));
+        }
+    }
+
+    for reference in repo.references_glob(&format!("refs/heads/{}/*", name))? {
+        let reference = reference?;
+        if reference.name_bytes() != own_ref.as_bytes() {
+            return Ok(Some(
+                String::from_utf8_lossy(reference.shorthand_bytes()).into_owned(),
+            ));
+        }
+    }
+
+    Ok(None)
+}
+
+/// How a branch compares to the branch it tracks, always `None` for remote-tracking branches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpstreamStatus {
+    /// No upstream is configured.
+    None,
+    /// The upstream is configured but no longer exists, a strong hint the branch is done with.
+    Gone,
+    InSync,
+    Ahead(usize),
+    Behind(usize),
+    Diverged {
+        ahead: usize,
+        behind: usize,
+    },
+}
+
+/// A branch that was deleted, the commit it pointed at stays around until git garbage collects it.
+#[derive(Clone)]
+pub struct DeletedBranch {
+    /// The name for display, shown lossily when it isn't valid UTF-8.
+    pub name: String,
+    /// The name exactly as git has it, which is what the branch is restored as.
+    pub raw_name: Vec<u8>,
+    pub id: Oid,
+    pub is_remote: bool,
+}
+
+impl DeletedBranch {
+    /// The git command that recreates the branch. Names that aren't valid UTF-8 are spelled
+    /// out with `printf` escapes so the shell passes git the original bytes.
+    pub fn undo_command(&self) -> String {
+        let name = match std::str::from_utf8(&self.raw_name) {
+            Ok(name) => name.to_string(),
+            Err(_) => format!("\"$(printf '{}')\"", printf_escape(&self.raw_name)),
+        };
+
+        if self.is_remote {
+            format!("git update-ref refs/remotes/{} {}", name, self.id)
+        } else {
+            format!("git branch {} {}", name, self.id)
+        }
+    }
+
+    /// Recreates the branch pointing at the commit it had when it was deleted.
+    pub fn restore(&self, repo: &Repository) -> Result<()> {
+        let name = match std::str::from_utf8(&self.raw_name) {
+            Ok(name) => name,
+            Err(_) => return self.restore_loose(repo),
+        };
+
+        if self.is_remote {
+            let name = format!("refs/remotes/{}", name);
+            repo.reference(&name, self.id, false, "arborist: restore deleted branch")?;
+        } else {
+            let commit = repo.find_commit(self.id)?;
+            repo.branch(name, &commit, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the ref file by hand, git2 only creates refs whose names are valid UTF-8.
+    #[cfg(unix)]
+    fn restore_loose(&self, repo: &Repository) -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        repo.find_commit(self.id)?;
+        let namespace = if self.is_remote {
+            "refs/remotes"
+        } else {
+            "refs/heads"
+        };
+        let path = repo
+            .path()
+            .join(namespace)
+            .join(OsStr::from_bytes(&self.raw_name));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        let mut file = match file {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                let message = format!("a branch named '{}' already exists", self.name);
+                return Err(
+                    git2::Error::new(ErrorCode::Exists, ErrorClass::Reference, message).into(),
+                );
+            }
+            Err(error) => return Err(error.into()),
+        };
+        io::Write::write_all(&mut file, format!("{}\n", self.id).as_bytes())?;
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn restore_loose(&self, _repo: &Repository) -> Result<()> {
+        Err(String::from_utf8(self.raw_name.clone()).unwrap_err().into())
+    }
+}
+
+/// Escapes bytes for a single-quoted `printf` format, everything but plain ASCII letters,
+/// digits and a few punctuation characters as an octal `\ooo`.
+fn printf_escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+/// Everything that can go wrong while scanning or acting on branches.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Crossterm(#[from] crossterm::ErrorKind),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+
+    #[error(transparent)]
+    FromUtf8(#[from] FromUtf8Error),
+
+    #[error("There is no branch named '{0}'")]
+    BranchNotFound(String),
+
+    #[error("Not inside a git repository (arborist must be run from within a repo)")]
+    NotARepository,
+
+    #[error("'{0}' is not a git repository or inside one")]
+    NotARepositoryAt(String),
+
+    #[error("Couldn't delete '{name}': {source}")]
+    DeleteFailed { name: String, source: git2::Error },
+
+    #[error("Can't name a branch '{name}' while there is a branch named '{existing}'")]
+    NameConflict { name: String, existing: String },
+
+    #[error("Pushing '{name}' was rejected: {reason}")]
+    PushRejected { name: String, reason: String },
+
+    #[error("Invalid input, Don't know what '{0}' means")]
+    InvalidInput(char),
+
+    #[error("Unknown argument '{0}'")]
+    UnknownArgument(String),
+
+    #[error("Missing value for '{0}'")]
+    MissingValue(&'static str),
+
+    #[error("Invalid value '{value}' for '{flag}'")]
+    InvalidValue { flag: &'static str, value: String },
+
+    #[error("Invalid action on line {line}: '{content}', expected '<branch>=d' or '<branch>=k'")]
+    InvalidAction { line: usize, content: String },
+
+    #[error("Invalid recording on line {line}: '{content}', expected '<branch> <key>'")]
+    InvalidRecording { line: usize, content: String },
+
+    #[error("'{0}' was recorded in raw mode, replaying it needs a terminal as stdin")]
+    ReplayNeedsTerminal(String),
+
+    #[error("Invalid config file on line {line}: {message}")]
+    InvalidConfig { line: usize, message: String },
+}
+
+/// What to do with a branch, parsed from a single keypress.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BranchAction {
+    Keep,
+    Protect,
+    Delete,
+    /// Deletes the upstream on the remote as well.
+    DeleteRemote,
+    DeleteAll,
+    Edit,
+    Undo,
+    Log,
+    View,
+    Push,
+    Archive,
+    /// Shows how the branch compares to another one.
+    Compare,
+    /// Attaches a note to the branch that's shown in later runs.
+    Note,
+    /// Takes the action taken on the previous branch again.
+    Repeat,
+    /// Switches to the branch and ends the review.
+    Checkout,
+    /// Deletes every branch marked so far with `--mark` or `--deferred`.
+    Apply,
+    Search,
+    Quit,
+}
+
+impl TryFrom<char> for BranchAction {
+    type Error = Error;
+
+    /// Uses the default key bindings, see `KeyBindings::action` for custom ones.
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        KeyBindings::default().action(value)
+    }
+}
+
+impl FromStr for BranchAction {
+    type Err = ();
+
+    /// Parses the names key bindings are configured by, like `delete_all`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "keep" => Ok(BranchAction::Keep),
+            "protect" => Ok(BranchAction::Protect),
+            "delete" => Ok(BranchAction::Delete),
+            "delete_remote" => Ok(BranchAction::DeleteRemote),
+            "delete_all" => Ok(BranchAction::DeleteAll),
+            "edit" => Ok(BranchAction::Edit),
+            "undo" => Ok(BranchAction::Undo),
+            "log" => Ok(BranchAction::Log),
+            "view" => Ok(BranchAction::View),
+            "push" => Ok(BranchAction::Push),
+            "archive" => Ok(BranchAction::Archive),
+            "compare" => Ok(BranchAction::Compare),
+            "note" => Ok(BranchAction::Note),
+            "repeat" => Ok(BranchAction::Repeat),
+            "checkout" => Ok(BranchAction::Checkout),
+            "apply" => Ok(BranchAction::Apply),
+            "search" => Ok(BranchAction::Search),
+            "quit" => Ok(BranchAction::Quit),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which key triggers each action, one key per action.
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    keys: Vec<(BranchAction, char)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            keys: vec![
+                (BranchAction::Keep, 'k'),
+                (BranchAction::Protect, 's'),
+                (BranchAction::Delete, 'd'),
+                (BranchAction::DeleteRemote, 'D'),
+                (BranchAction::DeleteAll, 'a'),
+                (BranchAction::Edit, 'e'),
+                (BranchAction::Undo, 'u'),
+                (BranchAction::Log, 'l'),
+                (BranchAction::View, 'v'),
+                (BranchAction::Push, 'p'),
+                (BranchAction::Archive, 't'),
+                (BranchAction::Compare, 'c'),
+                (BranchAction::Note, 'n'),
+                (BranchAction::Repeat, '.'),
+                (BranchAction::Checkout, 'o'),
+                (BranchAction::Apply, 'w'),
+                (BranchAction::Quit, 'q'),
+                (BranchAction::Search, '/'),
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Moves `action` to `key`, the key it had before no longer does anything.
+    pub fn bind(&mut self, action: BranchAction, key: char) {
+        for binding in self.keys.iter_mut() {
+            if binding.0 == action {
+                binding.1 = key;
+            }
+        }
+    }
+
+    /// The key that triggers `action`.
+    pub fn key(&self, action: BranchAction) -> char {
+        self.keys
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map(|&(_, key)| key)
+            .expect("every action has a key")
+    }
+
+    /// Every action with its key, in the order they're listed in prompts.
+    pub fn iter(&self) -> impl Iterator<Item = (BranchAction, char)> + '_ {
+        self.keys.iter().copied()
+    }
+
+    /// An action bound to the same key as an earlier one, if there is one.
+    pub fn conflict(&self) -> Option<BranchAction> {
+        self.keys
+            .iter()
+            .enumerate()
+            .find_map(|(index, &(action, key))| {
+                let earlier = &self.keys[..index];
+                earlier
+                    .iter()
+                    .any(|(_, other)| *other == key)
+                    .then_some(action)
+            })
+    }
+
+    /// Capital letters that aren't bound themselves fall back to the lowercase key, so the
+    /// commands still work with caps lock on.
+    pub fn action(&self, key: char) -> Result<BranchAction> {
+        let find = |key: char| self.keys.iter().find(|&&(_, bound)| bound == key);
+
+        find(key)
+            .or_else(|| find(key.to_ascii_lowercase()))
+            .map(|&(action, _)| action)
+            .ok_or(Error::InvalidInput(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A throwaway repository with a single commit on `master`, deleted again when dropped.
+    struct TestRepo {
+        path: PathBuf,
+        repo: Repository,
+    }
+
+    impl TestRepo {
+        fn new(name: &str) -> TestRepo {
+            let path =
+                std::env::temp_dir().join(format!("arborist-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            let mut init = git2::RepositoryInitOptions::new();
+            init.initial_head("master");
+            let repo = Repository::init_opts(&path, &init).unwrap();
+
+            {
+                let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+                let tree = repo.treebuilder(None).unwrap().write().unwrap();
+                let tree = repo.find_tree(tree).unwrap();
+                repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+                    .unwrap();
+            }
+
+            TestRepo { path, repo }
+        }
+
+        fn head(&self) -> Oid {
+            self.repo.head().unwrap().target().unwrap()
+        }
+
+        /// Makes a commit on top of `parent` at `seconds` in UTC+2 without moving any branch.
+        fn commit_at(&self, parent: Oid, seconds: i64) -> Oid {
+            let time = git2::Time::new(seconds, 120);
+            let signature = git2::Signature::new("Test", "test@example.com", &time).unwrap();
+            let parent = self.repo.find_commit(parent).unwrap();
+            let tree = parent.tree().unwrap();
+            self.repo
+                .commit(None, &signature, &signature, "work", &tree, &[&parent])
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn names(scan: &Scan) -> Vec<String> {
+        scan.branches
+            .iter()
+            .map(|branch| branch.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn created_time_is_where_the_branch_split_off() {
+        let test = TestRepo::new("created");
+        let split = test.commit_at(test.head(), 1_000_000);
+        let branch = test.commit_at(split, 2_000_000);
+        let base = test.commit_at(split, 3_000_000);
+
+        let created = created_time(&test.repo, branch, base).unwrap().unwrap();
+        assert_eq!(created.timestamp(), 1_000_000);
+        assert_eq!(created.offset().local_minus_utc(), 2 * 60 * 60);
+        assert_eq!(
+            created_time(&test.repo, branch, branch)
+                .unwrap()
+                .unwrap()
+                .timestamp(),
+            2_000_000
+        );
+    }
+
+    #[test]
+    fn keeping_a_branch_leaves_others_at_the_same_commit() {
+        let test = TestRepo::new("kept");
+        let tip = test.repo.find_commit(test.head()).unwrap();
+        test.repo.branch("kept", &tip, false).unwrap();
+        test.repo.branch("sibling", &tip, false).unwrap();
+        let moved = test.commit_at(test.head(), 1_000_000);
+        test.repo
+            .branch("moved", &test.repo.find_commit(moved).unwrap(), false)
+            .unwrap();
+
+        let options = ScanOptions {
+            kept: vec![
+                ("kept".to_string(), test.head()),
+                ("moved".to_string(), test.head()),
+            ]
+            .into_iter()
+            .collect(),
+            protected: ["master".to_string()].iter().cloned().collect(),
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+        assert_eq!(names(&scan), ["moved", "sibling"]);
+    }
+
+    #[test]
+    fn renaming_into_another_branchs_path_keeps_the_branch() {
+        let test = TestRepo::new("rename-conflict");
+        let tip = test.repo.find_commit(test.head()).unwrap();
+        test.repo.branch("trash", &tip, false).unwrap();
+        test.repo.branch("feature", &tip, false).unwrap();
+
+        let scan = get_branches(&test.repo, &ScanOptions::default()).unwrap();
+        let mut branches = scan.branches;
+        let feature = branches.iter_mut().find(|b| b.name == "feature").unwrap();
+        assert!(matches!(
+            feature.rename(&test.repo, "trash/feature"),
+            Err(Error::NameConflict { existing, .. }) if existing == "trash"
+        ));
+        assert_eq!(feature.name, "feature");
+        assert!(test.repo.find_branch("feature", BranchType::Local).is_ok());
+
+        let trash = branches.iter_mut().find(|b| b.name == "trash").unwrap();
+        trash.rename(&test.repo, "trash/trash").unwrap();
+        assert!(test
+            .repo
+            .find_branch("trash/trash", BranchType::Local)
+            .is_ok());
+    }
+
+    #[test]
+    fn unborn_head_has_no_commit() {
+        let test = TestRepo::new("unborn");
+        assert_eq!(head_commit(&test.repo).unwrap(), Some(test.head()));
+
+        test.repo.set_head("refs/heads/orphan").unwrap();
+        assert_eq!(head_commit(&test.repo).unwrap(), None);
+    }
+
+    #[test]
+    fn divergence_counts_both_sides_of_the_split() {
+        let test = TestRepo::new("divergence");
+        let split = test.commit_at(test.head(), 1_000_000);
+        let ours = test.commit_at(test.commit_at(split, 2_000_000), 3_000_000);
+        let theirs = test.commit_at(split, 4_000_000);
+        test.repo
+            .branch("theirs", &test.repo.find_commit(theirs).unwrap(), false)
+            .unwrap();
+
+        let compared = divergence(&test.repo, ours, "theirs", false).unwrap();
+        assert_eq!(compared.ahead, 2);
+        assert_eq!(compared.behind, 1);
+        assert_eq!(compared.merge_base, Some(split));
+        assert!(matches!(
+            divergence(&test.repo, ours, "missing", false),
+            Err(Error::BranchNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn first_parent_counts_skip_what_merges_brought_in() {
+        let test = TestRepo::new("first-parent");
+        let base = test.head();
+        let side = test.commit_at(test.commit_at(base, 1_000_000), 2_000_000);
+        let main = test.commit_at(base, 3_000_000);
+
+        let time = git2::Time::new(4_000_000, 120);
+        let signature = git2::Signature::new("Test", "test@example.com", &time).unwrap();
+        let parents = [
+            test.repo.find_commit(main).unwrap(),
+            test.repo.find_commit(side).unwrap(),
+        ];
+        let tree = parents[0].tree().unwrap();
+        let merge = test
+            .repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "merge",
+                &tree,
+                &[&parents[0], &parents[1]],
+            )
+            .unwrap();
+
+        assert_eq!(
+            ahead_behind(&test.repo, merge, base, false).unwrap(),
+            (4, 0)
+        );
+        assert_eq!(ahead_behind(&test.repo, merge, base, true).unwrap(), (2, 0));
+
+        let all = BaseCommits::new(&test.repo, base, false).unwrap();
+        let first_parent = BaseCommits::new(&test.repo, base, true).unwrap();
+        assert_eq!(all.unique_commits(&test.repo, merge).unwrap(), 4);
+        assert_eq!(first_parent.unique_commits(&test.repo, merge).unwrap(), 2);
+    }
+
+    #[test]
+    fn branches_built_on_another_are_stacked_on_it() {
+        let test = TestRepo::new("stacked");
+        let below = test.commit_at(test.head(), 1_000_000);
+        let above = test.commit_at(below, 2_000_000);
+        let beside = test.commit_at(test.head(), 3_000_000);
+        for (name, id) in [
+            ("merged", test.head()),
+            ("below", below),
+            ("above", above),
+            ("beside", beside),
+        ] {
+            let commit = test.repo.find_commit(id).unwrap();
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let scan = get_branches(&test.repo, &ScanOptions::default()).unwrap();
+        let base = BaseCommits::new(&test.repo, test.head(), false).unwrap();
+        let mut stacks = Stacks::new(&scan.branches, &base);
+        let branch = |name| scan.branches.iter().find(|b| b.name == name).unwrap();
+        assert_eq!(
+            stacks.above(&test.repo, branch("below")).unwrap(),
+            ["above"]
+        );
+        assert_eq!(
+            stacks.below(&test.repo, branch("above")).unwrap(),
+            ["below"]
+        );
+        assert!(stacks
+            .above(&test.repo, branch("merged"))
+            .unwrap()
+            .is_empty());
+        assert!(stacks
+            .above(&test.repo, branch("beside"))
+            .unwrap()
+            .is_empty());
+
+        stacks.remove("above");
+        assert!(stacks
+            .above(&test.repo, branch("below"))
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Writes a loose ref by hand, git2 only creates refs from valid UTF-8 names.
+    #[cfg(unix)]
+    fn write_invalid_branch(test: &TestRepo) {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"bad-\xff-name");
+        let path = test.path.join(".git/refs/heads").join(name);
+        fs::write(path, format!("{}\n", test.head())).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_names_are_skipped() {
+        let test = TestRepo::new("invalid-skip");
+        write_invalid_branch(&test);
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        test.repo.branch("fine", &commit, false).unwrap();
+
+        let options = ScanOptions {
+            sort: SortKey::Name,
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["fine", "master"]);
+        assert_eq!(scan.invalid_names, vec!["bad-\u{fffd}-name"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_names_can_be_shown_lossily() {
+        let test = TestRepo::new("invalid-lossy");
+        write_invalid_branch(&test);
+
+        let options = ScanOptions {
+            lossy_names: true,
+            sort: SortKey::Name,
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["bad-\u{fffd}-name", "master"]);
+        assert!(scan.invalid_names.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn lossy_names_are_restored_with_their_original_bytes() {
+        let test = TestRepo::new("invalid-restore");
+        write_invalid_branch(&test);
+
+        let options = ScanOptions {
+            lossy_names: true,
+            include: vec!["bad-*".to_string()],
+            ..ScanOptions::default()
+        };
+        let mut scan = get_branches(&test.repo, &options).unwrap();
+        let deleted = scan.branches[0].delete().unwrap();
+
+        assert_eq!(deleted.raw_name, b"bad-\xff-name");
+        assert_eq!(
+            deleted.undo_command(),
+            format!("git branch \"$(printf 'bad-\\377-name')\" {}", test.head())
+        );
+        deleted.restore(&test.repo).unwrap();
+        let scan = get_branches(&test.repo, &options).unwrap();
+        assert_eq!(names(&scan), vec!["bad-\u{fffd}-name"]);
+        assert!(matches!(
+            deleted.restore(&test.repo),
+            Err(Error::Git(error)) if error.code() == ErrorCode::Exists
+        ));
+    }
+
+    #[test]
+    fn unreachable_commits_leave_out_what_refs_still_reach() {
+        let test = TestRepo::new("unreachable");
+        let shared = test.commit_at(test.head(), 1_000_000);
+        let dangling = test.commit_at(shared, 2_000_000);
+        let commit = test.repo.find_commit(shared).unwrap();
+        test.repo.branch("shared", &commit, false).unwrap();
+
+        assert_eq!(unreachable_commits(&test.repo, &[dangling]).unwrap(), 1);
+        assert_eq!(unreachable_commits(&test.repo, &[shared]).unwrap(), 0);
+    }
+
+    #[test]
+    fn pushing_updates_an_upstream_with_another_name() {
+        let test = TestRepo::new("push-upstream");
+        let remote_path = test.path.join("remote.git");
+        let remote = Repository::init_bare(&remote_path).unwrap();
+        test.repo
+            .remote("origin", remote_path.to_str().unwrap())
+            .unwrap();
+
+        let tip = test.commit_at(test.head(), 1_000_000);
+        test.repo
+            .branch("feat", &test.repo.find_commit(tip).unwrap(), false)
+            .unwrap();
+        let mut config = test.repo.config().unwrap();
+        config.set_str("branch.feat.remote", "origin").unwrap();
+        config
+            .set_str("branch.feat.merge", "refs/heads/other")
+            .unwrap();
+
+        let options = ScanOptions {
+            include: vec!["feat".to_string()],
+            ..ScanOptions::default()
+        };
+        let mut scan = get_branches(&test.repo, &options).unwrap();
+        scan.branches[0].push(&test.repo, "origin").unwrap();
+
+        assert_eq!(remote.refname_to_id("refs/heads/other").unwrap(), tip);
+        assert!(remote.find_reference("refs/heads/feat").is_err());
+    }
+
+    #[test]
+    fn archiving_picks_a_free_tag_name() {
+        let test = TestRepo::new("archive");
+        let old = test.repo.find_commit(test.head()).unwrap();
+        let new = test.commit_at(test.head(), 1_000_000);
+        test.repo
+            .tag_lightweight("archive/topic", old.as_object(), false)
+            .unwrap();
+        test.repo
+            .branch("topic", &test.repo.find_commit(new).unwrap(), false)
+            .unwrap();
+
+        let options = ScanOptions {
+            include: vec!["topic".to_string()],
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+        let branch = &scan.branches[0];
+
+        assert_eq!(branch.archive(&test.repo).unwrap(), "archive/topic-2");
+        // Archiving again finds the tag that's already there.
+        assert_eq!(branch.archive(&test.repo).unwrap(), "archive/topic-2");
+        assert_eq!(
+            test.repo
+                .refname_to_id("refs/tags/archive/topic-2")
+                .unwrap(),
+            new
+        );
+    }
+
+    #[test]
+    fn checking_out_moves_head_to_the_branch() {
+        let test = TestRepo::new("checkout");
+        let tip = test.commit_at(test.head(), 1_000_000);
+        test.repo
+            .branch("topic", &test.repo.find_commit(tip).unwrap(), false)
+            .unwrap();
+
+        let options = ScanOptions {
+            include: vec!["topic".to_string()],
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+        assert_eq!(scan.branches[0].refname(), "refs/heads/topic");
+        assert!(!has_uncommitted_changes(&test.repo).unwrap());
+        scan.branches[0].checkout(&test.repo).unwrap();
+
+        assert_eq!(test.repo.head().unwrap().shorthand(), Some("topic"));
+        assert_eq!(test.head(), tip);
+    }
+
+    #[test]
+    fn branches_can_be_taken_one_at_a_time() {
+        let test = TestRepo::new("lazy");
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        for name in &["one", "two", "three"] {
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let options = ScanOptions {
+            exclude: vec!["t*".to_string()],
+            ..ScanOptions::default()
+        };
+        let mut found: Vec<String> = Branches::new(&test.repo, &options)
+            .unwrap()
+            .map(|branch| branch.unwrap().name)
+            .collect();
+        found.sort();
+        assert_eq!(found, vec!["master", "one"]);
+
+        let first = Branches::new(&test.repo, &options).unwrap().take(1).count();
+        assert_eq!(first, 1);
+    }
+
+    #[test]
+    fn excluded_branches_are_left_out() {
+        let test = TestRepo::new("exclude");
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        for name in &["release/1.0", "release/2.0", "feature/release"] {
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let options = ScanOptions {
+            exclude: vec!["release/*".to_string(), "mast?r".to_string()],
+            sort: SortKey::Name,
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["feature/release"]);
+        assert_eq!(scan.found, 4);
+    }
+
+    #[test]
+    fn equal_commit_times_are_ordered_by_name() {
+        let test = TestRepo::new("sort-ties");
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        for name in &["c", "a", "B", "b"] {
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let scan = get_branches(&test.repo, &ScanOptions::default()).unwrap();
+        assert_eq!(names(&scan), vec!["B", "a", "b", "c", "master"]);
+
+        let options = ScanOptions {
+            sort: SortKey::Name,
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+        assert_eq!(names(&scan), vec!["a", "B", "b", "c", "master"]);
+    }
+
+    #[test]
+    fn sorting_by_unique_commits_puts_the_emptiest_first() {
+        let test = TestRepo::new("sort-unique");
+        let one = test.commit_at(test.head(), 3_000_000);
+        let two = test.commit_at(test.commit_at(test.head(), 1_000_000), 2_000_000);
+        for (name, id) in &[("two", two), ("one", one), ("none", test.head())] {
+            let commit = test.repo.find_commit(*id).unwrap();
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let options = ScanOptions {
+            sort: SortKey::Unique,
+            protected: ["master".to_string()].iter().cloned().collect(),
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["none", "one", "two"]);
+    }
+
+    #[test]
+    fn failed_deletions_name_the_branch() {
+        let test = TestRepo::new("delete-failed");
+        let mut scan = get_branches(&test.repo, &ScanOptions::default()).unwrap();
+
+        // Git refuses to delete the branch HEAD points at.
+        match scan.branches[0].delete() {
+            Err(Error::DeleteFailed { name, .. }) => assert_eq!(name, "master"),
+            _ => panic!("deleting the current branch should fail"),
+        }
+    }
+
+    #[test]
+    fn branches_already_in_not_merged_into_are_left_out() {
+        let test = TestRepo::new("not-merged");
+        let behind = test.repo.find_commit(test.head()).unwrap();
+        let ahead = test
+            .repo
+            .find_commit(test.commit_at(behind.id(), 1_000_000))
+            .unwrap();
+        test.repo.branch("behind", &behind, false).unwrap();
+        test.repo.branch("ahead", &ahead, false).unwrap();
+
+        let options = ScanOptions {
+            not_merged_into: Some(test.head()),
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["ahead"]);
+    }
+
+    #[test]
+    fn protected_names_can_be_patterns() {
+        let test = TestRepo::new("protect-glob");
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        for name in &["release/1.0", "hotfix", "hotfixes"] {
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let options = ScanOptions {
+            protected: ["master", "release/*", "hotfix"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["hotfixes"]);
+    }
+
+    #[test]
+    fn arboristignore_leaves_out_matches_unless_negated_later() {
+        let test = TestRepo::new("arboristignore");
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        for name in &["release/1.0", "release/2.0", "!odd", "topic"] {
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+        fs::write(
+            test.path.join(".arboristignore"),
+            "# shipped with the repo\n\nrelease/*\n!release/2.0\n\\!odd\nmaster\n",
+        )
+        .unwrap();
+
+        let options = ScanOptions {
+            sort: SortKey::Name,
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["release/2.0", "topic"]);
+    }
+
+    #[test]
+    fn included_branches_are_the_only_ones_but_exclude_wins() {
+        let test = TestRepo::new("include");
+        let commit = test.repo.find_commit(test.head()).unwrap();
+        for name in &["feature/a", "feature/b", "fix/c"] {
+            test.repo.branch(name, &commit, false).unwrap();
+        }
+
+        let options = ScanOptions {
+            include: vec!["feature/*".to_string()],
+            exclude: vec!["*/b".to_string()],
+            sort: SortKey::Name,
+            ..ScanOptions::default()
+        };
+        let scan = get_branches(&test.repo, &options).unwrap();
+
+        assert_eq!(names(&scan), vec!["feature/a"]);
+    }
+
+    #[test]
+    fn try_from_maps_every_command_key() {
+        let expected = [
+            ('k', BranchAction::Keep),
+            ('s', BranchAction::Protect),
+            ('d', BranchAction::Delete),
+            ('D', BranchAction::DeleteRemote),
+            ('a', BranchAction::DeleteAll),
+            ('e', BranchAction::Edit),
+            ('u', BranchAction::Undo),
+            ('l', BranchAction::Log),
+            ('v', BranchAction::View),
+            ('p', BranchAction::Push),
+            ('t', BranchAction::Archive),
+            ('c', BranchAction::Compare),
+            ('n', BranchAction::Note),
+            ('.', BranchAction::Repeat),
+            ('o', BranchAction::Checkout),
+            ('w', BranchAction::Apply),
+            ('q', BranchAction::Quit),
+            ('/', BranchAction::Search),
+        ];
+
+        for (key, action) in expected.iter() {
+            assert_eq!(BranchAction::try_from(*key).unwrap(), *action);
+        }
+    }
+
+    #[test]
+    fn try_from_ignores_case() {
+        assert_eq!(BranchAction::try_from('K').unwrap(), BranchAction::Keep);
+        assert_eq!(
+            BranchAction::try_from('A').unwrap(),
+            BranchAction::DeleteAll
+        );
+        assert_eq!(BranchAction::try_from('Q').unwrap(), BranchAction::Quit);
+    }
+
+    #[test]
+    fn keys_bound_with_their_case_win() {
+        assert_eq!(BranchAction::try_from('d').unwrap(), BranchAction::Delete);
+        assert_eq!(
+            BranchAction::try_from('D').unwrap(),
+            BranchAction::DeleteRemote
+        );
+    }
+
+    #[test]
+    fn custom_bindings_replace_the_default_key() {
+        let mut keys = KeyBindings::default();
+        keys.bind(BranchAction::Delete, 'x');
+
+        assert_eq!(keys.action('x').unwrap(), BranchAction::Delete);
+        assert!(keys.action('d').is_err());
+        assert_eq!(keys.key(BranchAction::Delete), 'x');
+    }
+
+    #[test]
+    fn conflicting_bindings_are_detected() {
+        let mut keys = KeyBindings::default();
+        assert_eq!(keys.conflict(), None);
+
+        keys.bind(BranchAction::Delete, 'k');
+        assert_eq!(keys.conflict(), Some(BranchAction::Delete));
+    }
+
+    #[test]
+    fn try_from_rejects_unknown_keys() {
+        match BranchAction::try_from('x') {
+            Err(Error::InvalidInput('x')) => {}
+            other => panic!("expected InvalidInput('x'), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_input_message() {
+        assert_eq!(
+            Error::InvalidInput('x').to_string(),
+            "Invalid input, Don't know what 'x' means"
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::{self, Write};
+
+/// Writes log records to stderr, separately from what the review itself prints. Lines end in
+/// `\r\n` since they show up in the middle of a raw mode session.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let _ = write!(
+                io::stderr(),
+                "[{} {}] {}\r\n",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Turns logging on at `debug` with `--verbose`, or at whatever level `RUST_LOG` names like
+/// `info` or `trace`. Normal runs log nothing.
+pub fn init(verbose: bool) {
+    let level = match std::env::var("RUST_LOG") {
+        Ok(level) => level.trim().parse().unwrap_or(LevelFilter::Off),
+        Err(_) => LevelFilter::Off,
+    };
+    let level = if verbose {
+        level.max(LevelFilter::Debug)
+    } else {
+        level
+    };
+
+    if log::set_logger(&Logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,45 +1,334 @@
-use chrono::prelude::*;
-use chrono::Duration;
-use crossterm::style::{style, Attribute, Color};
+use arborist_git::{
+    created_time, delete_remote_branch, divergence, get_branches, glob, has_uncommitted_changes,
+    head_commit, is_merged_into, is_pushed, is_reachable_elsewhere, recent_commits, resolve_base,
+    show_commit, unreachable_commits, BaseCommits, Branch, BranchAction, DeletedBranch, Error,
+    KeepFile, KeyBindings, NoteFile, ProtectFile, Result, Scan, ScanOptions, SessionFile, SortKey,
+    Stacks, UpstreamStatus, DEFAULT_PROTECTED,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Color, ContentStyle, StyledContent};
 use crossterm::terminal;
-use git2::{BranchType, Oid, Repository};
-use std::convert::TryFrom;
+use crossterm::tty::IsTty;
+use git2::{BranchType, ErrorCode, Oid, Repository};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::{Bytes, Read, Stdin, Stdout, Write};
-use std::string::FromUtf8Error;
+use std::io::{Stdout, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-type Result<T, E = Error> = std::result::Result<T, E>;
+mod config;
+mod export;
+mod logger;
+mod session;
+mod tui;
+
+use config::Config;
+use session::Input;
 
 fn main() {
-    let result = (|| -> Result<_> {
-        let repo = Repository::open_from_env()?;
-        terminal::enable_raw_mode()?;
+    let result = (|| -> Result<i32> {
+        let mut args = Args::parse(Config::load()?)?;
+        logger::init(args.verbose);
+        let repo = match &args.path {
+            Some(path) => Repository::discover(path).map_err(|error| match error.code() {
+                ErrorCode::NotFound => Error::NotARepositoryAt(path.clone()),
+                _ => error.into(),
+            })?,
+            None => Repository::open_from_env().map_err(|error| match error.code() {
+                ErrorCode::NotFound => Error::NotARepository,
+                _ => error.into(),
+            })?,
+        };
+
+        if args.restore_last {
+            restore_last_session(&repo)?;
+            return Ok(0);
+        }
+
+        // Every answer comes from here, from the base question on.
+        let recording = match &args.replay {
+            Some(path) => Some(session::load(path)?),
+            None => None,
+        };
+        let mut stdin = match &recording {
+            Some(recording) => {
+                // The keys only make sense read the way they were typed. Recordings from
+                // before the mode was noted down were all made in raw mode.
+                let mode = recording.input_mode.unwrap_or(InputMode::Raw);
+                args.input_mode = args.input_mode.or(Some(mode));
+                Input::replay(recording.keys.clone())
+            }
+            None => Input::terminal(),
+        };
+        // Raw mode can't be turned on without a terminal, even though the keys don't come
+        // from it.
+        if let (Some(path), false) = (&args.replay, args.line_mode()) {
+            if !io::stdin().is_tty() {
+                return Err(Error::ReplayNeedsTerminal(path.clone()));
+            }
+        }
+        if let Some(path) = &args.record {
+            let mode = if args.line_mode() {
+                InputMode::Line
+            } else {
+                InputMode::Raw
+            };
+            stdin.record_to(File::create(path)?, mode)?;
+        }
 
-        let mut app = App::new();
+        let chosen_base = match (&args.merged, args.interactive_base) {
+            (Some(Some(_)), _) | (_, false) => None,
+            _ => Some(choose_base(
+                &repo,
+                &mut stdin,
+                args.line_mode(),
+                args.echo(),
+            )?),
+        };
+        let merged_into = match (&args.merged, chosen_base) {
+            (Some(None), Some(chosen)) => Some(chosen),
+            (Some(base), _) => Some(resolve_base(&repo, base.as_deref())?),
+            (None, _) => None,
+        };
+        let explicit_base = merged_into.or(chosen_base);
+        let mut keeps = KeepFile::open(&repo)?;
+        if args.reset_keeps {
+            keeps.reset()?;
+        }
 
-        let mut branches = get_branches(&repo)?;
+        let protects = ProtectFile::open(&repo)?;
+        let protected: BTreeSet<String> = args
+            .protected
+            .iter()
+            .chain(protects.names())
+            .cloned()
+            .collect();
 
-        if branches.is_empty() {
-            write!(
-                app.stdout,
-                "{}\r\n",
-                style("Found no branches (master is ignored)")
-                    .with(Color::Yellow)
-                    .attribute(Attribute::Dim)
-            )?;
+        let trash_pattern = format!("{}/*", TRASH);
+        let mut exclude = args.exclude.clone();
+        let mut include = args.include.clone();
+        if let Some(Prune::Trash) = args.prune {
+            include = vec![trash_pattern];
+        } else if args.trash {
+            // Whatever is in the trash was already dealt with.
+            exclude.push(trash_pattern);
+        }
+
+        // An unborn HEAD has no history for branches to be part of.
+        let not_merged_into = if args.skip_head_ancestors {
+            head_commit(&repo)?
         } else {
-            for branch in &mut branches {
-                act_on_branch(branch, &mut app)?;
+            None
+        };
+
+        let options = ScanOptions {
+            merged_into,
+            not_merged_into,
+            older_than: args.older_than,
+            since: args.since,
+            until: args.until,
+            protected: protected.clone(),
+            exclude,
+            include,
+            local_only: args.local_only,
+            remotes: args.remotes,
+            kept: keeps.kept().clone(),
+            sort: args.sort,
+            base: explicit_base,
+            first_parent: args.first_parent,
+            reverse: args.reverse,
+            limit: args.limit,
+            group: args.group,
+            lossy_names: args.lossy_names,
+            only: args.branch.clone(),
+        };
+
+        if args.count_only {
+            let count = get_branches(&repo, &options)?.branches.len();
+            writeln!(io::stdout(), "{}", count)?;
+            return Ok(0);
+        }
+
+        if args.list {
+            let branches = get_branches(&repo, &options)?.branches;
+            // Output that goes to a file or another program is left whole and plain.
+            let width = args.width.or_else(|| match terminal::size() {
+                Ok((columns, _)) if columns > 0 && io::stdout().is_tty() => {
+                    Some(usize::from(columns))
+                }
+                _ => None,
+            });
+            let stdout = io::stdout();
+            export::write_table(
+                &mut stdout.lock(),
+                &branches,
+                width,
+                args.color && io::stdout().is_tty(),
+                args.full_ref,
+            )?;
+            return Ok(0);
+        }
+
+        if let Some(format) = args.format {
+            let branches = get_branches(&repo, &options)?.branches;
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            match format {
+                Format::Json => export::write_json(&mut stdout, &branches)?,
+                Format::Csv => export::write_csv(&mut stdout, &branches, args.hash_len)?,
             }
+            return Ok(0);
         }
 
-        Ok(())
+        let Scan {
+            mut branches,
+            found,
+            omitted,
+            invalid_names,
+        } = get_branches(&repo, &options)?;
+
+        let actions = match (&args.delete_pattern, &args.actions) {
+            // Deleting by pattern is scripted too, with every matching local branch marked for
+            // deletion and the rest left out of the review altogether.
+            (Some(pattern), _) => {
+                branches.retain(|branch| !branch.is_remote && glob::matches(pattern, &branch.name));
+                let actions = branches
+                    .iter()
+                    .map(|branch| (branch.name.clone(), BranchAction::Delete))
+                    .collect();
+                Some(actions)
+            }
+            (None, Some(path)) => Some(load_actions(path)?),
+            (None, None) => None,
+        };
+
+        // Scripted runs never read from the terminal, so they work without one.
+        if actions.is_none() && !args.line_mode() {
+            terminal::enable_raw_mode()?;
+        }
+
+        // What auto-deleting and counting unique commits compare against, there's nothing to
+        // compare against while HEAD is unborn.
+        let base = match explicit_base {
+            Some(base) => Some(base),
+            None => head_commit(&repo)?,
+        };
+        let mut app = App::new(&args, &repo, base, keeps, protects, actions, stdin)?;
+        app.stacks = app
+            .base_commits
+            .as_ref()
+            .map(|base_commits| Stacks::new(&branches, base_commits));
+
+        let names: Vec<String> = branches.iter().map(|branch| branch.name.clone()).collect();
+        if let (Some(path), Some(recording)) = (&args.replay, &recording) {
+            if let Some(recorded) = &recording.branches {
+                let missing: Vec<&str> = recorded
+                    .iter()
+                    .filter(|name| !names.contains(name))
+                    .map(String::as_str)
+                    .collect();
+                let added: Vec<&str> = names
+                    .iter()
+                    .filter(|name| !recorded.contains(name))
+                    .map(String::as_str)
+                    .collect();
+                if !missing.is_empty() || !added.is_empty() {
+                    let message = format!(
+                        "The branches changed since '{}' was recorded, gone: {}, new: {}",
+                        path,
+                        list_or_none(&missing),
+                        list_or_none(&added)
+                    );
+                    write!(app.stdout, "{}\r\n", app.paint(message, Tone::Warning))?;
+                }
+            }
+        }
+        if args.record.is_some() {
+            app.stdin.record_branches(&names)?;
+        }
+
+        if let Some(timeout) = app.timeout {
+            let seconds = match timeout.as_secs() {
+                1 => "1 second".to_string(),
+                seconds => format!("{} seconds", seconds),
+            };
+            let message = format!(
+                "Branches are kept when there's no answer within {}",
+                seconds
+            );
+            app.note(message)?;
+        }
+
+        for name in &invalid_names {
+            let message = format!(
+                "Skipping '{}' because its name isn't valid UTF-8, use --lossy-names to review it",
+                name
+            );
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        }
+
+        if omitted > 0 {
+            let message = format!("Left out {} because of --limit", branch_count(omitted));
+            app.note(message)?;
+        }
+
+        if !branches.is_empty() {
+            // The branches `--limit` left out were already reported.
+            let left_out = found.saturating_sub(branches.len() + omitted);
+            let message = format!(
+                "Found {}, reviewing {} after filters ({} protected, excluded or filtered out)",
+                branch_count(found),
+                branches.len(),
+                left_out
+            );
+            app.note(message)?;
+        }
+
+        if let (Some(pattern), true) = (&args.delete_pattern, branches.is_empty()) {
+            let message = format!("No branches match '{}'", pattern);
+            app.note(message)?;
+        } else if branches.is_empty() {
+            let protected: Vec<_> = protected.into_iter().collect();
+            let message = format!("Found no branches ({} ignored)", protected.join(", "));
+            app.note(message)?;
+        } else if let Some(prune) = app.prune {
+            match prune {
+                Prune::Gone => prune_gone(&repo, &mut branches, &mut app)?,
+                Prune::Identical => prune_identical(&repo, &mut branches, &mut app)?,
+                Prune::Trash => empty_trash(&repo, &mut branches, &mut app)?,
+            }
+        } else if app.tui && app.actions.is_none() {
+            tui::review_branches(&repo, &mut branches, &mut app)?;
+        } else {
+            review_branches(&repo, &mut branches, &mut app)?;
+        }
+
+        if app.gc_hint && !app.deleted_tips.is_empty() {
+            write_gc_hint(&repo, &mut app)?;
+        }
+
+        if app.quit {
+            Ok(EXIT_QUIT)
+        } else if app.summary.deleted == 0 {
+            Ok(EXIT_NOTHING_DELETED)
+        } else {
+            Ok(0)
+        }
     })();
 
     terminal::disable_raw_mode().ok();
 
     match result {
-        Ok(()) => {}
+        Ok(code) => std::process::exit(code),
+        // Whoever was reading the output is gone, usually because the terminal or a pager it
+        // was piped into was closed, so there's nobody left to tell.
+        Err(Error::Io(error)) if error.kind() == io::ErrorKind::BrokenPipe => std::process::exit(0),
         Err(error) => {
             eprintln!("{}", error);
             std::process::exit(1);
@@ -47,188 +336,2577 @@ fn main() {
     }
 }
 
-fn act_on_branch(branch: &mut Branch, app: &mut App) -> Result<()> {
-    if branch.is_head {
-        let head_message = style(format!(
-            "Ignoring '{}' because it is the current branch",
-            branch.name
-        ))
-        .with(Color::Yellow)
-        .attribute(Attribute::Dim);
-        write!(app.stdout, "{}\r\n", head_message)?;
+/// The names joined with commas, or "none".
+fn list_or_none(names: &[&str]) -> String {
+    if names.is_empty() {
+        "none".to_string()
     } else {
-        match get_branch_action_from_user(app, &branch)? {
-            BranchAction::Quit => return Ok(()),
-            BranchAction::Keep => {}
-            BranchAction::Delete => {
-                branch.delete()?;
+        names.join(", ")
+    }
+}
+
+/// Exits with this when nothing was deleted, whether there was nothing to delete or every
+/// branch was kept.
+const EXIT_NOTHING_DELETED: i32 = 2;
+/// Exits with this when the review was quit before reaching the last branch.
+const EXIT_QUIT: i32 = 3;
+
+fn review_branches(repo: &Repository, branches: &mut Vec<Branch>, app: &mut App) -> Result<()> {
+    let mut delete_all = false;
+    let mut index = 0;
+    let mut current_group = None;
+
+    while index < branches.len() {
+        let total = branches.len();
+        let branch = &mut branches[index];
+
+        let group = branch.group().map(String::from);
+        if app.group && current_group.as_ref() != Some(&group) {
+            write_group_header(group.as_deref(), app)?;
+            current_group = Some(group);
+        }
+
+        if delete_all && !branch.is_head && !branch.in_worktree {
+            delete_branch(repo, branch, app)?;
+            index += 1;
+            continue;
+        }
+
+        let progress = (index + 1, total);
+        match act_on_branch(repo, branch, app, progress)? {
+            // Everything after this picked the old HEAD as what's current, so the review stops.
+            BranchAction::Checkout => {
+                app.quit = true;
+                break;
+            }
+            BranchAction::Quit => {
+                // The branch the prompt was showing wasn't decided on either.
                 let message = format!(
-                    "Deleted branch '{}', to undo run `git branch {} {}`",
-                    branch.name, branch.name, branch.id
+                    "Quit with {} of {} branches left unreviewed",
+                    total - index,
+                    total
                 );
+                app.note(message)?;
+                app.quit = true;
+                break;
+            }
+            // The current branch is part of the batch, it's deleted on the next pass or offered
+            // again when the batch wasn't confirmed.
+            BranchAction::DeleteAll => {
+                delete_all = confirm_bulk_delete(&branches[index..], app)?;
+                continue;
+            }
+            // The current branch is part of the search too, so it's offered again if it matches.
+            BranchAction::Search => {
+                search_remaining(branches, index, app)?;
+                continue;
+            }
+            // The current branch is offered again unless it was one of the marked ones, whose
+            // marks are gone either way once they were asked about.
+            BranchAction::Apply => {
+                if app.marked.is_empty() {
+                    app.note("Nothing is marked for deletion")?;
+                    continue;
+                }
+                let current = app.marked.contains(&branches[index].name);
+                delete_marked(repo, branches, app)?;
+                if !current {
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        index += 1;
+    }
+
+    if !app.marked.is_empty() {
+        delete_marked(repo, branches, app)?;
+    }
+
+    write_summary(repo, app)
+}
+
+/// Deletes every branch whose upstream is gone after listing them and asking once. Branches
+/// with commits that aren't on any remote are left alone unless `--force` is given.
+fn prune_gone(repo: &Repository, branches: &mut [Branch], app: &mut App) -> Result<()> {
+    let mut gone = Vec::new();
+
+    for branch in branches.iter_mut() {
+        if branch.upstream != UpstreamStatus::Gone || branch.is_head || branch.in_worktree {
+            continue;
+        }
+
+        if !app.force && !is_pushed(repo, branch)? {
+            let message = format!(
+                "Skipping '{}' because it has commits that are not on any remote, use --force to delete it",
+                branch.name
+            );
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+            app.summary.skipped += 1;
+            continue;
+        }
+
+        gone.push(branch);
+    }
+
+    if gone.is_empty() {
+        app.note("Found no branches whose upstream is gone")?;
+        return write_summary(repo, app);
+    }
+
+    delete_together(repo, "Upstream gone:", gone, app)
+}
+
+/// Deletes every branch without a single commit of its own, compared to the `--merged` base or
+/// HEAD, after listing them and asking once. Nothing is lost with them since the base has it
+/// all, unlike merged branches that got new commits afterwards.
+fn prune_identical(repo: &Repository, branches: &mut [Branch], app: &mut App) -> Result<()> {
+    let base_commits = match &app.base_commits {
+        Some(base_commits) => base_commits,
+        None => {
+            app.note("HEAD has no commits yet, so there's nothing to compare branches against")?;
+            return write_summary(repo, app);
+        }
+    };
+    let mut identical = Vec::new();
+
+    for branch in branches.iter_mut() {
+        if branch.is_head || branch.in_worktree {
+            continue;
+        }
+
+        if base_commits.unique_commits(repo, branch.id)? == 0 {
+            identical.push(branch);
+        }
+    }
 
-                let styled_message = style(message).with(Color::Yellow).attribute(Attribute::Dim);
+    if identical.is_empty() {
+        app.note("Found no branches without commits of their own")?;
+        return write_summary(repo, app);
+    }
+
+    delete_together(repo, "No commits of their own:", identical, app)
+}
+
+/// Deletes every branch that `--trash` moved to the trash after listing them and asking once.
+fn empty_trash(repo: &Repository, branches: &mut [Branch], app: &mut App) -> Result<()> {
+    let trashed = branches
+        .iter_mut()
+        .filter(|branch| !branch.is_head && !branch.in_worktree)
+        .collect();
+    delete_together(repo, "In the trash:", trashed, app)
+}
+
+/// Lists `branches` under `heading` and deletes them all after one confirmation.
+fn delete_together(
+    repo: &Repository,
+    heading: &str,
+    branches: Vec<&mut Branch>,
+    app: &mut App,
+) -> Result<()> {
+    write!(app.stdout, "{}\r\n", app.paint(heading, Tone::Muted))?;
+    for branch in &branches {
+        write!(
+            app.stdout,
+            "  {}\r\n",
+            app.paint(&branch.name, Tone::Highlight)
+        )?;
+    }
+
+    let question = format!("Delete {}? (y/n) > ", branch_count(branches.len()));
+    write!(app.stdout, "{}", app.paint(question, Tone::Warning))?;
+
+    if !confirm(app)? {
+        app.summary.kept += branches.len();
+        return write_summary(repo, app);
+    }
+
+    for branch in branches {
+        remove_branch(repo, branch, app)?;
+    }
+
+    write_summary(repo, app)
+}
+
+/// Lists the local branches and asks which one `--merged` and the unique commit counts should
+/// compare against, where an empty answer keeps HEAD.
+fn choose_base(repo: &Repository, stdin: &mut Input, line_mode: bool, echo: bool) -> Result<Oid> {
+    let mut names = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        if let Some(name) = branch.name()? {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+
+    let mut stdout = io::stdout();
+
+    if !line_mode {
+        terminal::enable_raw_mode()?;
+    }
+    let chosen = (|| -> Result<Option<String>> {
+        for (index, name) in names.iter().enumerate() {
+            write!(stdout, "{:>3}) {}\r\n", index + 1, name)?;
+        }
+
+        loop {
+            write!(
+                stdout,
+                "Compare against which branch? (number, enter for HEAD) > "
+            )?;
+            stdout.flush()?;
 
-                write!(app.stdout, "{}\r\n", styled_message)?;
+            let answer = read_answer_line(&mut *stdin, &mut stdout, echo)?;
+
+            if answer.is_empty() {
+                return Ok(None);
+            }
+
+            match answer.parse::<usize>() {
+                Ok(number) if (1..=names.len()).contains(&number) => {
+                    return Ok(Some(names[number - 1].clone()))
+                }
+                _ => write!(stdout, "Pick a number from 1 to {}\r\n", names.len())?,
             }
         }
+    })();
+    if !line_mode {
+        terminal::disable_raw_mode()?;
     }
-    Ok(())
+
+    resolve_base(repo, chosen?.as_deref())
 }
 
-fn get_branch_action_from_user(app: &mut App, branch: &Branch) -> Result<BranchAction> {
-    let branch_name = style(format!("'{}'", branch.name)).with(Color::Green);
-    let commit_hash =
-        style(format!("({})", &branch.id.to_string()[0..10])).attribute(Attribute::Dim);
-    let commit_time = style(format!("{}", branch.time)).with(Color::Green);
-    let commands = style("(k/d/q/?)").attribute(Attribute::Bold);
+/// Asks to type "yes" before deleting `branches` all at once when there are more of them than
+/// `--bulk-threshold`, listing every one of them first.
+fn confirm_bulk_delete(branches: &[Branch], app: &mut App) -> Result<bool> {
+    let batch: Vec<&str> = branches
+        .iter()
+        .filter(|branch| !branch.is_head && !branch.in_worktree)
+        .map(|branch| branch.name.as_str())
+        .collect();
+
+    if batch.len() <= app.bulk_threshold {
+        return Ok(true);
+    }
 
     write!(
         app.stdout,
-        "{} {} last commit at {} {} > ",
-        branch_name, commit_hash, commit_time, commands
+        "{}\r\n",
+        app.paint("About to delete:", Tone::Muted)
     )?;
+    for name in &batch {
+        write!(app.stdout, "  {}\r\n", app.paint(name, Tone::Highlight))?;
+    }
+
+    let question = format!("Type yes to delete {} > ", branch_count(batch.len()));
+    write!(app.stdout, "{}", app.paint(question, Tone::Warning))?;
     app.stdout.flush()?;
 
-    let byte = match app.stdin.next() {
-        Some(byte) => byte?,
-        None => return get_branch_action_from_user(app, branch),
-    };
+    let answer = read_answer_line(&mut app.stdin, &mut app.stdout, app.echo)?;
 
-    let c = char::from(byte);
-    write!(app.stdout, "{}\r\n", c)?;
+    Ok(answer == "yes")
+}
 
-    if c == '?' {
-        write!(app.stdout, "\r\n")?;
-        write!(
-            app.stdout,
-            "{}\r\n",
-            style("Here are what the commands mean:").attribute(Attribute::Dim)
-        )?;
-        write!(
-            app.stdout,
-            "{} - Keep the branch\r\n",
-            style("k").attribute(Attribute::Bold)
-        )?;
-        write!(
-            app.stdout,
-            "{} - Delete the branch\r\n",
-            style("d").attribute(Attribute::Bold)
-        )?;
-        write!(
-            app.stdout,
-            "{} - Quit\r\n",
-            style("q").attribute(Attribute::Bold)
-        )?;
-        write!(
-            app.stdout,
-            "{} - Show this help text\r\n",
-            style("?").attribute(Attribute::Bold)
-        )?;
-        write!(app.stdout, "\r\n")?;
+/// Deletes every marked branch after one confirmation, the final step of `--deferred` and
+/// `--mark` or whenever the marks are applied in between.
+fn delete_marked(repo: &Repository, branches: &mut [Branch], app: &mut App) -> Result<()> {
+    let marked = std::mem::take(&mut app.marked);
+
+    write!(app.stdout, "\r\n")?;
+    write!(
+        app.stdout,
+        "{}\r\n",
+        app.paint("Marked for deletion:", Tone::Muted)
+    )?;
+    for name in &marked {
+        write!(app.stdout, "  {}\r\n", app.paint(name, Tone::Highlight))?;
+    }
+
+    let question = format!("Delete {}? (y/n) > ", branch_count(marked.len()));
+    write!(app.stdout, "{}", app.paint(question, Tone::Warning))?;
+
+    if !confirm(app)? {
+        app.summary.skipped += marked.len();
+        return Ok(());
+    }
+
+    // Deleting marks the branches again otherwise, and later ones still need that.
+    let deferred = std::mem::replace(&mut app.deferred, false);
+    for branch in branches.iter_mut() {
+        if marked.contains(&branch.name) {
+            delete_branch(repo, branch, app)?;
+        }
+    }
+    app.deferred = deferred;
+
+    Ok(())
+}
+
+fn write_group_header(group: Option<&str>, app: &mut App) -> Result<()> {
+    let header = match group {
+        Some(group) => format!("== {}/ ==", group),
+        None => "== (ungrouped) ==".to_string(),
+    };
+
+    if !app.quiet {
+        write!(app.stdout, "\r\n{}\r\n", app.paint(header, Tone::Key))?;
         app.stdout.flush()?;
-        get_branch_action_from_user(app, branch)
+    }
+    Ok(())
+}
+
+fn write_summary(repo: &Repository, app: &mut App) -> Result<()> {
+    let summary = &app.summary;
+    let deleted = if app.dry_run {
+        "would be deleted"
     } else {
-        BranchAction::try_from(c)
+        "deleted"
+    };
+    let mut message = format!(
+        "{} {}, {} kept, {} skipped",
+        summary.deleted, deleted, summary.kept, summary.skipped
+    );
+    // What `git gc` can reclaim eventually, commits still on another branch or tag don't count.
+    if !app.deleted_tips.is_empty() {
+        match unreachable_commits(repo, &app.deleted_tips)? {
+            1 => message += ", 1 commit reclaimed",
+            count => message += &format!(", {} commits reclaimed", count),
+        }
     }
+
+    if !app.quiet {
+        write!(app.stdout, "\r\n")?;
+    }
+    app.note(message)?;
+    Ok(())
+}
+
+/// Tells how many commits the session's deletions left unreachable and how to get rid of them.
+fn write_gc_hint(repo: &Repository, app: &mut App) -> Result<()> {
+    let message = match unreachable_commits(repo, &app.deleted_tips)? {
+        0 => "Every deleted commit is still reachable from another branch or tag".to_string(),
+        1 => "1 commit is no longer reachable from any branch or tag".to_string(),
+        count => format!(
+            "{} commits are no longer reachable from any branch or tag",
+            count
+        ),
+    };
+    app.note(message)?;
+    app.note("To reclaim their space run `git gc`, which keeps them until they're two weeks old")?;
+    // Pruning right away also throws out what the undo commands need.
+    let warning = "Pruning them sooner, like with `git gc --prune=now`, stops the `git branch <name> <id>` commands printed for the deleted branches from bringing them back";
+    write!(app.stdout, "{}\r\n", app.paint(warning, Tone::Warning))?;
+    Ok(())
 }
 
-fn get_branches(repo: &Repository) -> Result<Vec<Branch>> {
-    let mut brances = repo
-        .branches(Some(BranchType::Local))?
-        .map(|branch| -> Result<_> {
-            let (branch, _) = branch?;
-            let name = String::from_utf8(branch.name_bytes()?.to_vec())?;
+/// Asks for a search term and drops the unreviewed branches starting at `index` that don't
+/// contain it, leaving everything as is when nothing matches.
+fn search_remaining(branches: &mut Vec<Branch>, index: usize, app: &mut App) -> Result<()> {
+    write!(app.stdout, "Search > ")?;
+    app.stdout.flush()?;
+
+    let query = read_answer_line(&mut app.stdin, &mut app.stdout, app.echo)?;
 
-            let commit = branch.get().peel_to_commit()?;
+    if query.is_empty() {
+        return Ok(());
+    }
 
-            let time = commit.time();
-            let offset = Duration::minutes(i64::from(time.offset_minutes()));
-            let time = NaiveDateTime::from_timestamp(time.seconds(), 0) + offset;
+    let needle = query.to_lowercase();
 
-            Ok(Branch {
-                id: commit.id(),
-                time,
-                name,
-                is_head: branch.is_head(),
-                branch,
-            })
-        })
-        .filter(|branch| {
-            if let Ok(branch) = branch {
-                branch.name != "master"
+    let matches = branches[index..]
+        .iter()
+        .filter(|branch| branch.name.to_lowercase().contains(&needle))
+        .count();
+
+    let message = if matches == 0 {
+        format!("No branches match '{}'", query)
+    } else {
+        let remaining = branches.split_off(index);
+        branches.extend(
+            remaining
+                .into_iter()
+                .filter(|branch| branch.name.to_lowercase().contains(&needle)),
+        );
+        let noun = if matches == 1 {
+            "branch matches"
+        } else {
+            "branches match"
+        };
+        format!("{} {} '{}'", matches, noun, query)
+    };
+
+    app.note(message)?;
+    Ok(())
+}
+
+/// `progress` is the branch's position in the review and how many branches there are.
+fn act_on_branch(
+    repo: &Repository,
+    branch: &mut Branch,
+    app: &mut App,
+    progress: (usize, usize),
+) -> Result<BranchAction> {
+    if branch.is_head {
+        let message = format!(
+            "Ignoring '{}' because it is the current branch",
+            branch.name
+        );
+        app.note(message)?;
+        app.summary.skipped += 1;
+        return Ok(BranchAction::Keep);
+    }
+
+    if branch.in_worktree {
+        let message = format!(
+            "Ignoring '{}' because it is checked out in another worktree",
+            branch.name
+        );
+        app.note(message)?;
+        app.summary.skipped += 1;
+        return Ok(BranchAction::Keep);
+    }
+
+    if let Some(base) = app.auto_delete_base {
+        if is_merged_into(repo, branch.id, base)? {
+            log::info!(
+                "'{}': merged into {}, deleting without asking",
+                branch.name,
+                base
+            );
+            app.note(format!("'{}' is already merged", branch.name))?;
+            if app.mark {
+                app.note(format!("Marked '{}' for deletion", branch.name))?;
+                app.marked.push(branch.name.clone());
             } else {
-                true
+                delete_branch_asking(repo, branch, app, false)?;
             }
-        })
-        .collect::<Result<Vec<_>>>()?;
+            return Ok(BranchAction::Delete);
+        }
+    }
+
+    // Branches kept only because nothing was decided about them, left out of `--actions` or
+    // unanswered with `--timeout`, aren't remembered as kept and come up again next time.
+    let mut undecided = false;
+    let action = match &app.actions {
+        Some(actions) => match actions.get(&branch.name) {
+            Some(&action) => action,
+            None => {
+                undecided = true;
+                BranchAction::Keep
+            }
+        },
+        None => loop {
+            app.stdin.branch = branch.name.clone();
+            let action = match get_branch_action_from_user(repo, app, branch, progress)? {
+                Some(action) => action,
+                None => {
+                    undecided = true;
+                    break BranchAction::Keep;
+                }
+            };
+            match action {
+                BranchAction::Undo => undo_last_delete(repo, app)?,
+                BranchAction::Log => write_log(repo, branch, app)?,
+                BranchAction::View => app.page(&show_commit(repo, branch.id)?)?,
+                BranchAction::Push => push_branch(repo, branch, app)?,
+                BranchAction::Compare => compare_branch(repo, branch, app)?,
+                BranchAction::Note => note_branch(branch, app)?,
+                BranchAction::Delete if app.mark => toggle_mark(branch, app)?,
+                BranchAction::Repeat => match app.last_action {
+                    Some(action) => break action,
+                    None => app.note("There's no earlier action to repeat")?,
+                },
+                BranchAction::Checkout => {
+                    if checkout_branch(repo, branch, app)? {
+                        break BranchAction::Checkout;
+                    }
+                }
+                action => break action,
+            }
+        },
+    };
 
-    brances.sort_unstable_by_key(|branch| branch.time);
+    log::info!("'{}': {:?}", branch.name, action);
 
-    Ok(brances)
-}
+    if app.mark && app.marked.contains(&branch.name) {
+        match action {
+            // Moving on leaves the mark in place, so the branch isn't kept.
+            BranchAction::Keep => return Ok(action),
+            BranchAction::Protect
+            | BranchAction::DeleteRemote
+            | BranchAction::Archive
+            | BranchAction::Edit => app.marked.retain(|name| *name != branch.name),
+            _ => {}
+        }
+    }
 
-struct App {
-    stdin: Bytes<Stdin>,
-    stdout: Stdout,
-}
+    if undecided {
+        app.summary.kept += 1;
+        return Ok(action);
+    }
 
-impl App {
-    fn new() -> App {
-        App {
-            stdin: io::stdin().bytes(),
-            stdout: io::stdout(),
+    if let BranchAction::Keep
+    | BranchAction::Protect
+    | BranchAction::Delete
+    | BranchAction::DeleteRemote
+    | BranchAction::Archive = action
+    {
+        app.last_action = Some(action);
+    }
+
+    match action {
+        BranchAction::Quit
+        | BranchAction::Search
+        | BranchAction::DeleteAll
+        | BranchAction::Checkout
+        | BranchAction::Apply => {}
+        BranchAction::Keep => {
+            if !app.dry_run {
+                app.keeps.record(branch)?;
+            }
+            app.summary.kept += 1;
+        }
+        BranchAction::Protect => {
+            if !app.dry_run {
+                app.protects.record(branch)?;
+            }
+            let message = format!("'{}' won't be offered for deletion again", branch.name);
+            app.note(message)?;
+            app.summary.kept += 1;
+        }
+        BranchAction::Delete => delete_branch(repo, branch, app)?,
+        BranchAction::Archive => archive_branch(repo, branch, app)?,
+        BranchAction::DeleteRemote => delete_branch_and_upstream(repo, branch, app)?,
+        BranchAction::Edit => {
+            rename_branch(repo, branch, app)?;
+            app.summary.kept += 1;
+        }
+        BranchAction::Undo
+        | BranchAction::Log
+        | BranchAction::View
+        | BranchAction::Push
+        | BranchAction::Compare
+        | BranchAction::Note
+        | BranchAction::Repeat => {
+            unreachable!(
+                "undo, log, view, push, compare, note and repeat are handled while prompting"
+            )
         }
     }
-}
 
-struct Branch<'repo> {
-    id: Oid,
-    time: NaiveDateTime,
-    name: String,
-    is_head: bool,
-    branch: git2::Branch<'repo>,
+    Ok(action)
 }
 
-impl<'repo> Branch<'repo> {
-    fn delete(&mut self) -> Result<()> {
-        self.branch.delete().map_err(From::from)
+/// Pushes the branch to its upstream's remote, asking which remote to use when it has no
+/// upstream and there's more than one. A failed push is reported and the review goes on.
+fn push_branch(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    if branch.is_remote {
+        let message = format!("'{}' is a remote-tracking branch already", branch.name);
+        write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        return Ok(());
+    }
+
+    let remote = match branch.upstream_remote(repo)? {
+        Some(remote) => remote,
+        None => {
+            let remotes: Vec<String> = repo.remotes()?.iter().flatten().map(String::from).collect();
+            match remotes.as_slice() {
+                [] => {
+                    let message = "There are no remotes to push to";
+                    write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+                    return Ok(());
+                }
+                [remote] => remote.clone(),
+                _ => {
+                    write!(
+                        app.stdout,
+                        "Push to which remote? ({}) > ",
+                        remotes.join(", ")
+                    )?;
+                    app.stdout.flush()?;
+                    let remote = read_answer_line(&mut app.stdin, &mut app.stdout, app.echo)?;
+
+                    if remote.is_empty() {
+                        return Ok(());
+                    }
+                    remote
+                }
+            }
+        }
+    };
+
+    if app.dry_run {
+        return app.note(format!("Would push '{}' to {}", branch.name, remote));
+    }
+
+    match branch.push(repo, &remote) {
+        Ok(()) => app.note(format!("Pushed '{}' to {}", branch.name, remote)),
+        Err(error @ Error::Git(_)) | Err(error @ Error::PushRejected { .. }) => {
+            let message = format!("Couldn't push '{}': {}", branch.name, error);
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+            Ok(())
+        }
+        Err(error) => Err(error),
     }
 }
 
-#[derive(Debug, thiserror::Error)]
-enum Error {
-    #[error(transparent)]
-    CrosstermError(#[from] crossterm::ErrorKind),
+/// Deletes the branch like `delete_branch` and then offers to delete its upstream on the remote
+/// too, which other people might still use. Without an upstream it's a plain delete.
+fn delete_branch_and_upstream(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    // Deleting the branch removes its upstream config as well, so it's looked up first.
+    let upstream = branch.upstream_ref(repo)?;
+    let deleted = app.summary.deleted;
+    delete_branch(repo, branch, app)?;
+
+    let (remote, refname) = match upstream {
+        // Deferred and declined deletions leave the remote alone too.
+        Some(upstream) if app.summary.deleted > deleted => upstream,
+        _ => return Ok(()),
+    };
+    let name = format!(
+        "{}/{}",
+        remote,
+        refname.strip_prefix("refs/heads/").unwrap_or(&refname)
+    );
+
+    let question = format!(
+        "Also delete '{}' on the remote for everyone? (y/n) > ",
+        name
+    );
+    write!(app.stdout, "{}", app.paint(question, Tone::Warning))?;
+    if !confirm(app)? {
+        return Ok(());
+    }
 
-    #[error(transparent)]
-    IoError(#[from] io::Error),
+    if app.dry_run {
+        return app.note(format!("Would delete '{}' on the remote", name));
+    }
 
-    #[error(transparent)]
-    GitError(#[from] git2::Error),
+    match delete_remote_branch(repo, &remote, &refname) {
+        Ok(()) => app.note(format!("Deleted '{}' on the remote", name)),
+        Err(error @ Error::Git(_)) | Err(error @ Error::PushRejected { .. }) => {
+            let message = format!("Couldn't delete '{}' on the remote: {}", name, error);
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+            Ok(())
+        }
+        Err(error) => Err(error),
+    }
+}
 
-    #[error(transparent)]
-    FromUtf8Error(#[from] FromUtf8Error),
+/// Tags the branch before deleting it, the tag keeps every commit around so there's nothing
+/// to confirm even when they were never pushed.
+fn archive_branch(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    if app.dry_run {
+        let message = format!("Would tag '{}' as archive/{}", branch.name, branch.name);
+        app.note(message)?;
+    } else {
+        let tag = branch.archive(repo)?;
+        app.note(format!("Tagged '{}' as {}", branch.name, tag))?;
+    }
 
-    #[error("Invalid input, Don't know what '{0}' means")]
-    InvalidInput(char),
+    remove_branch(repo, branch, app)
 }
 
-enum BranchAction {
-    Keep,
-    Delete,
-    Quit,
+fn delete_branch(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    let interactive = app.actions.is_none();
+    delete_branch_asking(repo, branch, app, interactive)
 }
 
-impl TryFrom<char> for BranchAction {
-    type Error = Error;
+/// Deletes the branch after the safety checks. Unless `interactive` nothing is asked, stacked
+/// branches only get a warning and ones with unpushed commits are skipped without `--force`.
+fn delete_branch_asking(
+    repo: &Repository,
+    branch: &mut Branch,
+    app: &mut App,
+    interactive: bool,
+) -> Result<()> {
+    if app.deferred {
+        let message = format!("Marked '{}' for deletion", branch.name);
+        app.note(message)?;
+        app.marked.push(branch.name.clone());
+        return Ok(());
+    }
 
-    fn try_from(value: char) -> Result<Self, Self::Error> {
-        match value {
-            'k' => Ok(BranchAction::Keep),
-            'd' => Ok(BranchAction::Delete),
-            'q' => Ok(BranchAction::Quit),
-            _ => Err(Error::InvalidInput(value)),
+    // Nothing is lost by moving a branch to the trash, so there's nothing to confirm.
+    if app.trash && !branch.is_remote {
+        return remove_branch(repo, branch, app);
+    }
+
+    let stacked = match &app.stacks {
+        Some(stacks) => stacks.above(repo, branch)?,
+        None => Vec::new(),
+    };
+    let verb = if stacked.len() == 1 { "is" } else { "are" };
+    if !stacked.is_empty() && !interactive {
+        let message = format!(
+            "Deleting '{}' even though {} {} stacked on it",
+            branch.name,
+            stacked.join(", "),
+            verb
+        );
+        write!(app.stdout, "{}\r\n", app.paint(message, Tone::Warning))?;
+    } else if !stacked.is_empty() {
+        let warning = format!(
+            "{} {} stacked on '{}', delete it anyway? (y/n) > ",
+            stacked.join(", "),
+            verb,
+            branch.name
+        );
+        write!(app.stdout, "{}", app.paint(warning, Tone::Warning))?;
+
+        if !confirm(app)? {
+            app.summary.skipped += 1;
+            return Ok(());
         }
     }
+
+    let unpushed = !app.force && !is_pushed(repo, branch)?;
+    if unpushed && !interactive {
+        let message = format!(
+            "Skipping '{}' because it has commits that are not on any remote, use --force to delete it",
+            branch.name
+        );
+        write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        app.summary.skipped += 1;
+        return Ok(());
+    }
+
+    if unpushed {
+        let warning = format!(
+            "'{}' has commits that are not on any remote, delete it anyway? (y/n) > ",
+            branch.name
+        );
+        write!(app.stdout, "{}", app.paint(warning, Tone::Warning))?;
+
+        if !confirm(app)? {
+            app.summary.skipped += 1;
+            return Ok(());
+        }
+
+        // Unpushed commits might still be safe on another local branch, if they aren't this is
+        // the last chance to keep them.
+        if !is_reachable_elsewhere(repo, branch)? {
+            let warning = format!(
+                "No other branch or tag contains '{}', its commits become unreachable once it's deleted, really delete it? (y/n) > ",
+                branch.name
+            );
+            write!(app.stdout, "{}", app.paint(warning, Tone::Warning))?;
+
+            if !confirm(app)? {
+                app.summary.skipped += 1;
+                return Ok(());
+            }
+        }
+    }
+
+    if app.confirm_undo {
+        let question = format!(
+            "To get '{}' back after deleting it run `{}`, delete it? (y/n) > ",
+            branch.name,
+            branch.undo_command()
+        );
+        write!(app.stdout, "{}", app.paint(question, Tone::Warning))?;
+
+        if !confirm(app)? {
+            app.summary.skipped += 1;
+            return Ok(());
+        }
+    }
+
+    remove_branch(repo, branch, app)
+}
+
+/// The prefix `--trash` moves branches under instead of deleting them.
+const TRASH: &str = "trash";
+
+/// Renames the branch to `trash/<name>`, or `trash/<name>-2` and so on when that's taken. It
+/// counts as deleted, `--empty-trash` deletes it for good later. A branch that can't be moved
+/// is skipped, nothing asked before this expected it to be gone for good.
+fn trash_branch(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    let old_name = branch.name.clone();
+    let undo = branch.undo_command();
+
+    if app.dry_run {
+        let message = format!("Would move '{}' to {}/{}", old_name, TRASH, old_name);
+        app.note(message)?;
+        app.summary.deleted += 1;
+        return Ok(());
+    }
+
+    for attempt in 1.. {
+        let new_name = match attempt {
+            1 => format!("{}/{}", TRASH, old_name),
+            _ => format!("{}/{}-{}", TRASH, old_name, attempt),
+        };
+
+        let refusal = match branch.rename(repo, &new_name) {
+            Ok(()) => break,
+            Err(Error::Git(error)) if error.code() == ErrorCode::Exists => continue,
+            Err(error @ Error::NameConflict { .. }) => {
+                format!("Not moving '{}' to the trash: {}", old_name, error)
+            }
+            Err(error) => format!(
+                "Couldn't move '{}' to the trash: {}, if it's gone run `{}` to restore it",
+                old_name, error, undo
+            ),
+        };
+        write!(app.stdout, "{}\r\n", app.paint(refusal, Tone::Error))?;
+        app.summary.skipped += 1;
+        return Ok(());
+    }
+
+    log::info!("moved '{}' to '{}'", old_name, branch.name);
+    if app.quiet {
+        write!(app.stdout, "{}\r\n", old_name)?;
+    } else {
+        let message = format!(
+            "Moved '{}' to the trash, to restore it run `git branch -m {} {}`",
+            old_name, branch.name, old_name
+        );
+        app.note(message)?;
+    }
+    app.summary.deleted += 1;
+    Ok(())
+}
+
+/// Deletes the branch, or only reports it with `--dry-run`, without any safety checks. With
+/// `--trash` it's moved to the trash instead, unless that's what is being emptied.
+fn remove_branch(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    if app.trash && !branch.is_remote && !matches!(app.prune, Some(Prune::Trash)) {
+        return trash_branch(repo, branch, app);
+    }
+
+    if app.dry_run {
+        if app.quiet {
+            write!(app.stdout, "{}\r\n", branch.name)?;
+        } else {
+            app.note(format!("Would delete branch '{}'", branch.name))?;
+        }
+        app.summary.deleted += 1;
+        return Ok(());
+    }
+
+    if !run_pre_delete_hook(branch, app)? {
+        app.summary.kept += 1;
+        return Ok(());
+    }
+
+    let deleted = branch.delete()?;
+    log::info!("deleted '{}' at {}", deleted.name, deleted.id);
+    // There's no prompt to press u at in scripted runs, after the list view or when pruning.
+    let undo_hint = if app.actions.is_some() || app.tui || app.prune.is_some() {
+        "to undo run"
+    } else {
+        "to undo press u or run"
+    };
+    let message = format!(
+        "Deleted branch '{}', {} `{}`",
+        deleted.name,
+        undo_hint,
+        deleted.undo_command()
+    );
+
+    if app.quiet {
+        write!(app.stdout, "{}\r\n", deleted.name)?;
+    } else {
+        app.note(message)?;
+    }
+    if let Some(undo_log) = &mut app.undo_log {
+        writeln!(undo_log, "{}", deleted.undo_command())?;
+        undo_log.flush()?;
+    }
+
+    if let Some(stacks) = &mut app.stacks {
+        stacks.remove(&deleted.name);
+    }
+
+    app.session.record(&deleted)?;
+    app.deleted_tips.push(deleted.id);
+    app.last_deleted = Some(deleted);
+    app.summary.deleted += 1;
+    Ok(())
+}
+
+/// Runs `--pre-delete-hook` for the branch, showing what it printed, and tells whether the
+/// deletion may go ahead. Without a hook it always may.
+fn run_pre_delete_hook(branch: &Branch, app: &mut App) -> Result<bool> {
+    let hook = match &app.pre_delete_hook {
+        Some(hook) => hook,
+        None => return Ok(true),
+    };
+
+    // The hook gets the branch as "$1" and its id as "$2", like git's own hooks get theirs.
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", hook))
+        .arg("arborist-git")
+        .arg(&branch.name)
+        .arg(branch.id.to_string())
+        .stdin(Stdio::null())
+        .output();
+    log::debug!("pre-delete hook for '{}': {:?}", branch.name, output);
+
+    let output = match output {
+        Ok(output) => output,
+        Err(error) => {
+            let message = format!(
+                "Not deleting '{}', the pre-delete hook couldn't run: {}",
+                branch.name, error
+            );
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+            return Ok(false);
+        }
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        write!(app.stdout, "{}\r\n", app.paint(line, Tone::Muted))?;
+    }
+
+    if output.status.success() {
+        return Ok(true);
+    }
+
+    let failure = match output.status.code() {
+        Some(code) => format!("failed with exit status {}", code),
+        None => "was killed by a signal".to_string(),
+    };
+    let message = format!(
+        "Not deleting '{}', the pre-delete hook {}",
+        branch.name, failure
+    );
+    write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+    Ok(false)
+}
+
+/// Opens the `--undo-log` script for appending, starting it with a shebang when it's new.
+fn open_undo_log(path: &str) -> Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "#!/bin/sh")?;
+    }
+    Ok(file)
+}
+
+fn undo_last_delete(repo: &Repository, app: &mut App) -> Result<()> {
+    let message = match app.last_deleted.take() {
+        Some(deleted) => {
+            deleted.restore(repo)?;
+            app.session.forget(&deleted)?;
+            app.summary.deleted -= 1;
+            app.summary.kept += 1;
+            format!("Restored branch '{}' at {}", deleted.name, deleted.id)
+        }
+        None => "Nothing to undo".to_string(),
+    };
+
+    app.note(message)?;
+    Ok(())
+}
+
+/// Recreates every branch the last session deleted, skipping the ones that exist again and
+/// warning about the ones whose commits were garbage collected since.
+fn restore_last_session(repo: &Repository) -> Result<()> {
+    let deleted = SessionFile::load(repo)?;
+    let mut stdout = io::stdout();
+
+    if deleted.is_empty() {
+        writeln!(stdout, "The last session didn't delete any branches")?;
+        return Ok(());
+    }
+
+    let odb = repo.odb()?;
+    for deleted in deleted {
+        if !odb.exists(deleted.id) {
+            writeln!(
+                stdout,
+                "Can't restore '{}', its commit {} is gone, most likely garbage collected",
+                deleted.name, deleted.id
+            )?;
+            continue;
+        }
+
+        match deleted.restore(repo) {
+            Ok(()) => writeln!(
+                stdout,
+                "Restored branch '{}' at {}",
+                deleted.name, deleted.id
+            )?,
+            Err(Error::Git(error)) if error.code() == ErrorCode::Exists => writeln!(
+                stdout,
+                "Skipping '{}' because a branch with that name exists again",
+                deleted.name
+            )?,
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads an actions file where every line is `<branch>=d` or `<branch>=k`. Blank lines and
+/// lines starting with `#` are ignored.
+fn load_actions(path: &str) -> Result<HashMap<String, BranchAction>> {
+    let contents = fs::read_to_string(path)?;
+    let mut actions = HashMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let action = match line.rsplit_once('=') {
+            Some((name, "d")) => Some((name, BranchAction::Delete)),
+            Some((name, "k")) => Some((name, BranchAction::Keep)),
+            _ => None,
+        };
+
+        match action {
+            Some((name, action)) => {
+                actions.insert(name.trim().to_string(), action);
+            }
+            None => {
+                return Err(Error::InvalidAction {
+                    line: index + 1,
+                    content: line.to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+/// Waits for a single keypress and treats only 'y' as agreement.
+fn confirm(app: &mut App) -> Result<bool> {
+    if app.yes {
+        write!(app.stdout, "y\r\n")?;
+        return Ok(true);
+    }
+
+    app.stdout.flush()?;
+
+    let c = match read_answer(&mut app.stdin, app.line_mode) {
+        Some(c) => c?,
+        None => return Ok(false),
+    };
+
+    if c == CTRL_C {
+        write!(app.stdout, "^C\r\n")?;
+        return Ok(false);
+    }
+
+    if app.echo {
+        write!(app.stdout, "{}\r\n", c)?;
+    }
+
+    Ok(c == 'y')
+}
+
+fn rename_branch(repo: &Repository, branch: &mut Branch, app: &mut App) -> Result<()> {
+    if branch.is_remote {
+        let message = format!(
+            "'{}' is a remote-tracking branch and can't be renamed",
+            branch.name
+        );
+        write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        return Ok(());
+    }
+
+    write!(app.stdout, "New name for '{}' > ", branch.name)?;
+    app.stdout.flush()?;
+
+    let new_name = read_answer_line(&mut app.stdin, &mut app.stdout, app.echo)?;
+
+    if new_name.is_empty() {
+        return Ok(());
+    }
+
+    let old_name = branch.name.clone();
+
+    match branch.rename(repo, &new_name) {
+        Ok(()) => {
+            let message = format!("Renamed branch '{}' to '{}'", old_name, new_name);
+            app.note(message)?;
+        }
+        Err(Error::Git(error))
+            if error.code() == ErrorCode::Exists || error.code() == ErrorCode::InvalidSpec =>
+        {
+            let message = if error.code() == ErrorCode::Exists {
+                format!(
+                    "A branch named '{}' already exists, keeping '{}'",
+                    new_name, old_name
+                )
+            } else {
+                format!(
+                    "'{}' is not a valid branch name, keeping '{}'",
+                    new_name, old_name
+                )
+            };
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        }
+        Err(error @ Error::NameConflict { .. }) => {
+            let message = format!("{}, keeping '{}'", error, old_name);
+            write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        }
+        Err(error) => return Err(error),
+    }
+
+    Ok(())
+}
+
+/// The fewest characters of a branch name that the prompt shortens it to on narrow terminals.
+const MIN_NAME_WIDTH: usize = 12;
+
+/// What raw mode delivers instead of SIGINT.
+const CTRL_C: char = '\u{3}';
+/// Stands for a whole escape sequence, which none of the prompts have a use for.
+const ESCAPE: char = '\u{1b}';
+
+/// Consumes the rest of an escape sequence after its ESC byte: `ESC [` followed by parameters
+/// up to a final byte like the arrows send, `ESC O` and one byte like F1 to F4 send, or a
+/// single byte for Alt combinations.
+fn skip_escape_sequence(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> Result<()> {
+    match bytes.next().transpose()? {
+        Some(b'[') => {
+            while let Some(byte) = bytes.next().transpose()? {
+                if (0x40..=0x7e).contains(&byte) {
+                    break;
+                }
+            }
+        }
+        Some(b'O') => {
+            bytes.next().transpose()?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Like `read_char` but waits for a key that isn't part of an escape sequence.
+fn read_key(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> Option<Result<char>> {
+    loop {
+        match read_char(bytes)? {
+            Ok(ESCAPE) => continue,
+            c => return Some(c),
+        }
+    }
+}
+
+/// A key pressed in raw mode, or in line mode the first character of the next line that isn't
+/// empty.
+fn read_answer(
+    bytes: &mut impl Iterator<Item = io::Result<u8>>,
+    line_mode: bool,
+) -> Option<Result<char>> {
+    if !line_mode {
+        return read_key(bytes);
+    }
+
+    loop {
+        let mut line = Vec::new();
+        let mut ended = false;
+        for byte in &mut *bytes {
+            match byte {
+                Ok(b'\n') => {
+                    ended = true;
+                    break;
+                }
+                Ok(byte) => line.push(byte),
+                Err(error) => return Some(Err(error.into())),
+            }
+        }
+
+        if let Some(c) = String::from_utf8_lossy(&line).trim().chars().next() {
+            return Some(Ok(c));
+        }
+        if !ended {
+            return None;
+        }
+    }
+}
+
+/// The key pressed at a branch prompt. Running out of input, like when stdin is a file with no
+/// answers left, counts as pressing the key for quitting since nothing else will come.
+fn read_prompt_key(
+    bytes: &mut impl Iterator<Item = io::Result<u8>>,
+    keys: &KeyBindings,
+    line_mode: bool,
+) -> Result<char> {
+    match read_answer(bytes, line_mode) {
+        Some(c) => c,
+        None => Ok(keys.key(BranchAction::Quit)),
+    }
+}
+
+/// Shortens `text` to at most `max` characters, marking the cut with an ellipsis.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+
+    match max {
+        0 => String::new(),
+        _ => text.chars().take(max - 1).chain(Some('…')).collect(),
+    }
+}
+
+/// Reads one keypress worth of UTF-8, pulling in as many continuation bytes as the first byte
+/// announces. Bytes that don't form a valid character come back as U+FFFD, and keys like the
+/// arrows that send an escape sequence come back as a single `ESCAPE`.
+fn read_char(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> Option<Result<char>> {
+    let first = match bytes.next()? {
+        Ok(byte) => byte,
+        Err(error) => return Some(Err(error.into())),
+    };
+
+    if first == 0x1b {
+        return Some(skip_escape_sequence(bytes).map(|()| ESCAPE));
+    }
+
+    let width = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Some(Ok(char::REPLACEMENT_CHARACTER)),
+    };
+
+    let mut buffer = [first, 0, 0, 0];
+    for slot in &mut buffer[1..width] {
+        match bytes.next() {
+            Some(Ok(byte)) => *slot = byte,
+            Some(Err(error)) => return Some(Err(error.into())),
+            None => return Some(Ok(char::REPLACEMENT_CHARACTER)),
+        }
+    }
+
+    let c = std::str::from_utf8(&buffer[..width])
+        .ok()
+        .and_then(|decoded| decoded.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER);
+
+    Some(Ok(c))
+}
+
+/// Waits up to `timeout` for a character key, `None` when none was pressed in time. This goes
+/// through crossterm's events since the plain stdin reader can't give up after a while.
+fn read_key_within(timeout: Duration) -> Result<Option<char>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !event::poll(remaining)? {
+            return Ok(None);
+        }
+
+        if let Event::Key(KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers,
+        }) = event::read()?
+        {
+            if modifiers.contains(KeyModifiers::CONTROL) && c == 'c' {
+                return Ok(Some(CTRL_C));
+            }
+            return Ok(Some(c));
+        }
+    }
+}
+
+/// Reads a line of input and moves on to the next line, echoing it unless the terminal
+/// already did that itself.
+fn read_answer_line(
+    stdin: &mut impl Iterator<Item = io::Result<u8>>,
+    stdout: &mut impl Write,
+    echo: bool,
+) -> Result<String> {
+    if !echo {
+        return read_line(stdin, &mut io::sink());
+    }
+
+    let line = read_line(stdin, stdout)?;
+    write!(stdout, "\r\n")?;
+    Ok(line)
+}
+
+/// Reads a line of input while in raw mode, echoing it back since the terminal won't.
+fn read_line(
+    stdin: &mut impl Iterator<Item = io::Result<u8>>,
+    stdout: &mut impl Write,
+) -> Result<String> {
+    let mut line = Vec::new();
+
+    while let Some(byte) = stdin.next() {
+        match byte? {
+            b'\r' | b'\n' => break,
+            0x1b => skip_escape_sequence(stdin)?,
+            // Ctrl-C cancels the whole line.
+            0x03 => return Ok(String::new()),
+            0x7f | 0x08 => {
+                if line.pop().is_some() {
+                    write!(stdout, "\x08 \x08")?;
+                }
+            }
+            byte => {
+                line.push(byte);
+                stdout.write_all(&[byte])?;
+            }
+        }
+        stdout.flush()?;
+    }
+
+    Ok(String::from_utf8(line)?.trim().to_string())
+}
+
+/// The pager git would use, `None` when it's turned off with `cat` or an empty command.
+fn pager_command(repo: &Repository) -> Option<String> {
+    let pager = std::env::var("GIT_PAGER")
+        .ok()
+        .or_else(|| repo.config().ok()?.get_string("core.pager").ok())
+        .or_else(|| std::env::var("PAGER").ok())
+        .unwrap_or_else(|| "less".to_string());
+
+    match pager.trim() {
+        "" | "cat" => None,
+        _ => Some(pager),
+    }
+}
+
+fn run_pager(pager: &str, text: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(pager)
+        .stdin(Stdio::piped())
+        // Like git: quit right away when everything fits on one screen and keep colors.
+        .env(
+            "LESS",
+            std::env::var("LESS").unwrap_or_else(|_| "FRX".to_string()),
+        )
+        .spawn()?;
+
+    if let Some(stdin) = child.stdin.as_mut() {
+        // The user quitting the pager early closes the pipe, which is fine.
+        match stdin.write_all(text.as_bytes()) {
+            Err(error) if error.kind() != io::ErrorKind::BrokenPipe => return Err(error),
+            _ => {}
+        }
+    }
+    drop(child.stdin.take());
+
+    match child.wait()? {
+        status if status.success() => Ok(()),
+        _ => Err(io::Error::other("the pager failed")),
+    }
+}
+
+/// How many commits `l` shows.
+const LOG_LENGTH: usize = 10;
+
+fn write_log(repo: &Repository, branch: &Branch, app: &mut App) -> Result<()> {
+    let mut text = String::new();
+
+    for commit in recent_commits(repo, branch, LOG_LENGTH)? {
+        let short_id: String = commit.id().to_string().chars().take(app.hash_len).collect();
+        let summary = String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default());
+        text += &format!("  {} {}\n", app.paint(short_id, Tone::Muted), summary);
+    }
+
+    text.push('\n');
+    app.page(&text)
+}
+
+/// Asks for a note about the branch and saves it for the commit the branch is at, so it's
+/// shown until the branch moves on.
+fn note_branch(branch: &Branch, app: &mut App) -> Result<()> {
+    write!(app.stdout, "Note for '{}' > ", branch.name)?;
+    app.stdout.flush()?;
+    let note = read_answer_line(&mut app.stdin, &mut app.stdout, app.echo)?;
+
+    if app.dry_run {
+        app.note("Not saving notes with --dry-run")?;
+        return Ok(());
+    }
+
+    app.notes.record(branch, &note)?;
+    Ok(())
+}
+
+/// Marks the branch for deletion with `--mark`, or unmarks it when it already is. The prompt
+/// shows again afterwards so a mark can be taken back before moving on.
+fn toggle_mark(branch: &Branch, app: &mut App) -> Result<()> {
+    let message = match app.marked.iter().position(|name| *name == branch.name) {
+        Some(position) => {
+            app.marked.remove(position);
+            format!("Unmarked '{}', {} marked", branch.name, app.marked.len())
+        }
+        None => {
+            app.marked.push(branch.name.clone());
+            format!(
+                "Marked '{}' for deletion, {} marked",
+                branch.name,
+                app.marked.len()
+            )
+        }
+    };
+    app.note(message)
+}
+
+/// Switches to the branch, telling whether it did. Remote-tracking branches and a working
+/// tree with uncommitted changes are refused and the prompt shows again.
+fn checkout_branch(repo: &Repository, branch: &Branch, app: &mut App) -> Result<bool> {
+    let refusal = if branch.is_remote {
+        Some(format!(
+            "'{}' is a remote-tracking branch, check out a local branch instead",
+            branch.name
+        ))
+    } else if has_uncommitted_changes(repo)? {
+        Some(format!(
+            "Not checking out '{}' because there are uncommitted changes, commit or stash them first",
+            branch.name
+        ))
+    } else {
+        None
+    };
+    if let Some(refusal) = refusal {
+        write!(app.stdout, "{}\r\n", app.paint(refusal, Tone::Error))?;
+        return Ok(false);
+    }
+
+    if app.dry_run {
+        app.note(format!("Would check out '{}'", branch.name))?;
+        return Ok(false);
+    }
+
+    if let Err(error) = branch.checkout(repo) {
+        let message = format!("Couldn't check out '{}': {}", branch.name, error);
+        write!(app.stdout, "{}\r\n", app.paint(message, Tone::Error))?;
+        return Ok(false);
+    }
+
+    // The marks are still applied on the way out, but a checked out branch can't be deleted.
+    app.marked.retain(|name| *name != branch.name);
+    app.note(format!("Checked out '{}', ending the review", branch.name))?;
+    Ok(true)
+}
+
+/// Asks for another branch and shows how many commits each one has that the other doesn't,
+/// along with the commit they split off at.
+fn compare_branch(repo: &Repository, branch: &Branch, app: &mut App) -> Result<()> {
+    write!(app.stdout, "Compare with which branch? > ")?;
+    app.stdout.flush()?;
+    let other = read_answer_line(&mut app.stdin, &mut app.stdout, app.echo)?;
+
+    if other.is_empty() {
+        return Ok(());
+    }
+
+    let divergence = match divergence(repo, branch.id, &other, app.first_parent) {
+        Ok(divergence) => divergence,
+        Err(error @ Error::BranchNotFound(_)) => {
+            write!(
+                app.stdout,
+                "{}\r\n",
+                app.paint(error.to_string(), Tone::Error)
+            )?;
+            return Ok(());
+        }
+        Err(error) => return Err(error),
+    };
+
+    let split = match divergence.merge_base {
+        Some(merge_base) => {
+            let short_id: String = merge_base.to_string().chars().take(app.hash_len).collect();
+            format!("they split off at {}", short_id)
+        }
+        None => "they share no history".to_string(),
+    };
+    let commits = |count| match count {
+        1 => "1 commit".to_string(),
+        count => format!("{} commits", count),
+    };
+    let message = format!(
+        "'{}' is {} ahead of and {} behind '{}', {}",
+        branch.name,
+        commits(divergence.ahead),
+        commits(divergence.behind),
+        other,
+        split
+    );
+    app.note(message)
+}
+
+/// The action picked for the branch, `None` when the prompt went unanswered for `--timeout`.
+fn get_branch_action_from_user(
+    repo: &Repository,
+    app: &mut App,
+    branch: &Branch,
+    progress: (usize, usize),
+) -> Result<Option<BranchAction>> {
+    loop {
+        write_prompt(repo, app, branch, progress)?;
+
+        let c = match app.timeout {
+            Some(timeout) => match read_key_within(timeout)? {
+                Some(c) => c,
+                None => {
+                    write!(app.stdout, "\r\n")?;
+                    let message = format!("No answer in time, keeping '{}'", branch.name);
+                    app.note(message)?;
+                    return Ok(None);
+                }
+            },
+            None => read_prompt_key(&mut app.stdin, &app.keys, app.line_mode)?,
+        };
+
+        // Raw mode turns Ctrl-C into a plain byte instead of a signal, so it's treated as
+        // quitting which still restores the terminal on the way out.
+        if c == CTRL_C {
+            write!(app.stdout, "^C\r\n")?;
+            return Ok(Some(BranchAction::Quit));
+        }
+
+        if app.echo {
+            write!(app.stdout, "{}\r\n", c)?;
+        }
+
+        if c == '?' {
+            write_help(app)?;
+            continue;
+        }
+
+        return app.keys.action(c).map(Some);
+    }
+}
+
+fn write_prompt(
+    repo: &Repository,
+    app: &mut App,
+    branch: &Branch,
+    (position, total): (usize, usize),
+) -> Result<()> {
+    let counter = format!("[{}/{}]", position, total);
+    let branch_tone = if branch.is_remote {
+        Tone::Remote
+    } else {
+        Tone::Highlight
+    };
+    let name = app.branch_name(branch);
+    let branch_name = format!("'{}'", name);
+    let short_id: String = branch.id.to_string().chars().take(app.hash_len).collect();
+    let commit_hash = format!("({})", short_id);
+    let commit_time = if app.relative_time {
+        relative_time(Utc::now().signed_duration_since(branch.commit_time()))
+    } else {
+        format!("at {}", branch.time)
+    };
+    let created = match app.created_base {
+        Some(base) => match created_time(repo, branch.id, base)? {
+            Some(created) if app.relative_time => format!(
+                ", created {}",
+                relative_time(Utc::now().signed_duration_since(created))
+            ),
+            Some(created) => format!(", created at {}", created.naive_local()),
+            None => String::new(),
+        },
+        None => String::new(),
+    };
+    let (tracking, tracking_tone) = match branch.upstream {
+        _ if branch.is_remote => ("(remote)".to_string(), Tone::Muted),
+        UpstreamStatus::None => ("(no upstream)".to_string(), Tone::Muted),
+        UpstreamStatus::Gone => ("\u{2717} upstream gone".to_string(), Tone::Warning),
+        UpstreamStatus::InSync => ("= in sync".to_string(), Tone::Highlight),
+        UpstreamStatus::Ahead(ahead) => (format!("\u{2191}{} ahead", ahead), Tone::Note),
+        UpstreamStatus::Behind(behind) => (format!("\u{2193}{} behind", behind), Tone::Note),
+        UpstreamStatus::Diverged { ahead, behind } => (
+            format!("\u{2191}{} \u{2193}{} diverged", ahead, behind),
+            Tone::Note,
+        ),
+    };
+    let upstream = if app.show_upstream {
+        branch.upstream_name()?
+    } else {
+        None
+    };
+    let tracking = match upstream {
+        Some(upstream) => format!("{} -> {}", tracking, upstream),
+        None => tracking,
+    };
+    let unique = match &app.base_commits {
+        Some(base_commits) => match base_commits.unique_commits(repo, branch.id)? {
+            1 => " 1 unique commit".to_string(),
+            unique => format!(" {} unique commits", unique),
+        },
+        None => String::new(),
+    };
+    let note = match app.notes.get(branch) {
+        Some(note) => format!(" [note: {}]", note),
+        None => String::new(),
+    };
+    let mut stack = String::new();
+    if let Some(stacks) = &app.stacks {
+        let above = stacks.above(repo, branch)?;
+        if !above.is_empty() {
+            stack += &format!(" [base of {}]", above.join(", "));
+        }
+        let below = stacks.below(repo, branch)?;
+        if !below.is_empty() {
+            stack += &format!(" [stacked on {}]", below.join(", "));
+        }
+    }
+    let marks = match app.marked.len() {
+        _ if !app.mark => String::new(),
+        count if app.marked.contains(&branch.name) => format!(" [marked, {} in total]", count),
+        count => format!(" [{} marked]", count),
+    };
+    // A `/` key would run into the separators around it.
+    let keys: Vec<String> = app
+        .keys
+        .iter()
+        .map(|(_, key)| match key {
+            '/' => "slash".to_string(),
+            key => key.to_string(),
+        })
+        .collect();
+    let commands = format!("({}/?)", keys.join("/"));
+
+    // The subject gets whatever room is left on the line once everything else is in place,
+    // keeping one column free for the key that gets echoed back.
+    let fixed_width = format!(
+        "{} {} {} last commit {}{} by {} \"\"{} {}{}{}{} {} > ",
+        counter,
+        branch_name,
+        commit_hash,
+        commit_time,
+        created,
+        branch.author,
+        unique,
+        tracking,
+        stack,
+        note,
+        marks,
+        commands
+    )
+    .chars()
+    .count();
+    let width = app.width();
+    let subject = truncate(&branch.summary, width.saturating_sub(fixed_width + 1));
+
+    // When the line is too long even without a subject, which frees up the room its quotes
+    // took, the branch name gets shortened too but keeps enough to tell which branch it is.
+    let overflow = (fixed_width + 1 - " \"\"".len()).saturating_sub(width);
+    let branch_name = if overflow > 0 {
+        let room = name.chars().count().saturating_sub(overflow);
+        format!("'{}'", truncate(&name, room.max(MIN_NAME_WIDTH)))
+    } else {
+        branch_name
+    };
+    let subject = if subject.is_empty() {
+        String::new()
+    } else {
+        format!(" \"{}\"", subject)
+    };
+
+    write!(
+        app.stdout,
+        "{} {} {} last commit {}{} by {}{}{} {}{}{}{} {} > ",
+        app.paint(counter, Tone::Muted),
+        app.paint(branch_name, branch_tone),
+        app.paint(commit_hash, Tone::Muted),
+        app.paint(commit_time, Tone::Highlight),
+        app.paint(created, Tone::Highlight),
+        branch.author,
+        subject,
+        app.paint(unique, Tone::Muted),
+        app.paint(tracking, tracking_tone),
+        app.paint(stack, Tone::Warning),
+        app.paint(note, Tone::Note),
+        app.paint(marks, Tone::Warning),
+        app.paint(commands, Tone::Key)
+    )?;
+    app.stdout.flush()?;
+    Ok(())
+}
+
+/// "1 branch" or "`count` branches".
+fn branch_count(count: usize) -> String {
+    match count {
+        1 => "1 branch".to_string(),
+        count => format!("{} branches", count),
+    }
+}
+
+/// Roughly how long ago something happened, like "3 weeks ago", using the largest unit that
+/// fits at least once. Months are 30 days and years 365.
+fn relative_time(elapsed: chrono::Duration) -> String {
+    // Commits dated in the future by a skewed clock count as just made.
+    let seconds = elapsed.num_seconds().max(0);
+    let units = [
+        (365 * 24 * 60 * 60, "year"),
+        (30 * 24 * 60 * 60, "month"),
+        (7 * 24 * 60 * 60, "week"),
+        (24 * 60 * 60, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+    ];
+
+    for (length, unit) in units.iter() {
+        let count = seconds / length;
+        match count {
+            0 => continue,
+            1 => return format!("1 {} ago", unit),
+            _ => return format!("{} {}s ago", count, unit),
+        }
+    }
+
+    "just now".to_string()
+}
+
+fn write_help(app: &mut App) -> Result<()> {
+    let mut text = format!(
+        "\n{}\n",
+        app.paint("Here are what the commands mean:", Tone::Muted)
+    );
+
+    for (action, key) in app.keys.iter() {
+        let description = match action {
+            BranchAction::Keep => {
+                "Keep the branch for now, it's offered again once it has new commits".to_string()
+            }
+            BranchAction::Protect => {
+                "Keep the branch forever, it's added to the protected branches".to_string()
+            }
+            BranchAction::Delete if app.mark => {
+                "Mark or unmark the branch for deletion".to_string()
+            }
+            BranchAction::Delete => "Delete the branch".to_string(),
+            BranchAction::DeleteRemote => {
+                "Delete the branch and, after asking, its upstream on the remote".to_string()
+            }
+            BranchAction::DeleteAll => "Delete this and all remaining branches".to_string(),
+            BranchAction::Edit => "Edit the branch name".to_string(),
+            BranchAction::Undo => "Undo the last deletion".to_string(),
+            BranchAction::Log => format!("Show the last {} commits on the branch", LOG_LENGTH),
+            BranchAction::View => "Show the branch's last commit and its changes".to_string(),
+            BranchAction::Push => "Push the branch to its upstream or a remote".to_string(),
+            BranchAction::Archive => "Tag the branch as archive/<name> and delete it".to_string(),
+            BranchAction::Compare => {
+                "Show how far the branch is ahead of and behind another one".to_string()
+            }
+            BranchAction::Note => {
+                "Write a note about the branch, an empty one removes it".to_string()
+            }
+            BranchAction::Repeat => {
+                "Do the same as for the previous branch, like keeping or deleting it".to_string()
+            }
+            BranchAction::Checkout => "Check out the branch and quit".to_string(),
+            BranchAction::Apply => "Delete every branch marked so far".to_string(),
+            BranchAction::Search => {
+                "Only review the remaining branches matching a search".to_string()
+            }
+            BranchAction::Quit => "Quit".to_string(),
+        };
+        text += &format!("{} - {}\n", app.paint(key, Tone::Key), description);
+    }
+
+    text += &format!("{} - Show this help text\n\n", app.paint("?", Tone::Key));
+    app.page(&text)
+}
+
+struct Args {
+    dry_run: bool,
+    merged: Option<Option<String>>,
+    older_than: Option<i64>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    protected: Vec<String>,
+    format: Option<Format>,
+    force: bool,
+    remotes: bool,
+    reset_keeps: bool,
+    sort: SortKey,
+    reverse: bool,
+    actions: Option<String>,
+    color: bool,
+    limit: Option<usize>,
+    group: bool,
+    hash_len: usize,
+    auto_delete_merged: bool,
+    delete_pattern: Option<String>,
+    tui: bool,
+    undo_log: Option<String>,
+    lossy_names: bool,
+    keys: KeyBindings,
+    deferred: bool,
+    relative_time: bool,
+    yes: bool,
+    branch: Option<String>,
+    quiet: bool,
+    prune: Option<Prune>,
+    trash: bool,
+    interactive_base: bool,
+    show_created: bool,
+    exclude: Vec<String>,
+    include: Vec<String>,
+    show_gc_hint: bool,
+    timeout: Option<u64>,
+    /// The repository to work on instead of the one around the current directory.
+    path: Option<String>,
+    local_only: bool,
+    count_only: bool,
+    verbose: bool,
+    bulk_threshold: usize,
+    pre_delete_hook: Option<String>,
+    width: Option<usize>,
+    show_upstream: bool,
+    confirm_undo: bool,
+    skip_head_ancestors: bool,
+    record: Option<String>,
+    replay: Option<String>,
+    input_mode: Option<InputMode>,
+    list: bool,
+    restore_last: bool,
+    first_parent: bool,
+    mark: bool,
+    full_ref: bool,
+}
+
+impl Args {
+    /// Whether answers are read as lines without raw mode, picked by `--input-mode` or the
+    /// recording being replayed, or else whenever stdin or stdout isn't a terminal.
+    fn line_mode(&self) -> bool {
+        match self.input_mode {
+            Some(InputMode::Line) => true,
+            Some(InputMode::Raw) => false,
+            None => !(io::stdin().is_tty() && io::stdout().is_tty()),
+        }
+    }
+
+    /// Whether answers are echoed, which a terminal outside of raw mode already does itself
+    /// unless they're replayed.
+    fn echo(&self) -> bool {
+        self.replay.is_some() || !(self.line_mode() && io::stdin().is_tty())
+    }
+
+    /// Starts from the config file's settings and lets the command line override them.
+    fn parse(config: Config) -> Result<Args> {
+        Args::parse_from(config, std::env::args().skip(1))
+    }
+
+    fn parse_from(config: Config, raw: impl Iterator<Item = String>) -> Result<Args> {
+        let mut args = Args {
+            dry_run: config.dry_run.unwrap_or(false),
+            merged: None,
+            older_than: config.older_than,
+            since: None,
+            until: None,
+            protected: Vec::new(),
+            format: None,
+            force: config.force.unwrap_or(false),
+            remotes: config.remotes.unwrap_or(false),
+            reset_keeps: false,
+            sort: config.sort.unwrap_or_default(),
+            reverse: config.reverse.unwrap_or(false),
+            actions: None,
+            // https://no-color.org asks for any value, even an empty one, to turn colors off.
+            color: config
+                .color
+                .unwrap_or_else(|| std::env::var_os("NO_COLOR").is_none()),
+            limit: config.limit,
+            group: config.group.unwrap_or(false),
+            hash_len: config.hash_len.unwrap_or(10),
+            auto_delete_merged: config.auto_delete_merged.unwrap_or(false),
+            delete_pattern: None,
+            tui: config.tui.unwrap_or(false),
+            undo_log: None,
+            lossy_names: false,
+            keys: config.keys.clone(),
+            deferred: config.deferred.unwrap_or(false),
+            relative_time: config.relative_time.unwrap_or(false),
+            yes: false,
+            branch: None,
+            quiet: false,
+            prune: None,
+            trash: false,
+            interactive_base: false,
+            show_created: false,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            show_gc_hint: false,
+            timeout: None,
+            path: None,
+            local_only: false,
+            count_only: false,
+            verbose: false,
+            bulk_threshold: 5,
+            pre_delete_hook: None,
+            width: None,
+            show_upstream: false,
+            confirm_undo: false,
+            skip_head_ancestors: false,
+            record: None,
+            replay: None,
+            input_mode: None,
+            list: false,
+            restore_last: false,
+            first_parent: false,
+            mark: false,
+            full_ref: false,
+        };
+
+        let mut raw = raw.peekable();
+
+        while let Some(arg) = raw.next() {
+            match arg.as_str() {
+                "--dry-run" => args.dry_run = true,
+                "--no-color" => args.color = false,
+                "--force" => args.force = true,
+                "--remotes" => args.remotes = true,
+                "--reset-keeps" => args.reset_keeps = true,
+                "--sort" => {
+                    let sort = value_of(&mut raw, "--sort")?;
+                    args.sort = parse_value("--sort", &sort)?;
+                }
+                "--reverse" => args.reverse = true,
+                // The same as `--sort date --reverse`, so it also undoes an earlier `--sort name`.
+                "--newest-first" => {
+                    args.sort = SortKey::Date;
+                    args.reverse = true;
+                }
+                "--group" => args.group = true,
+                "--tui" => args.tui = true,
+                "--deferred" => args.deferred = true,
+                "--relative-time" => args.relative_time = true,
+                "--yes" => args.yes = true,
+                "--quiet" => args.quiet = true,
+                "--prune-gone" => args.prune = Some(Prune::Gone),
+                "--prune-identical" => args.prune = Some(Prune::Identical),
+                "--trash" => args.trash = true,
+                "--empty-trash" => args.prune = Some(Prune::Trash),
+                "--interactive-base" => args.interactive_base = true,
+                "--show-created" => args.show_created = true,
+                "--show-upstream" => args.show_upstream = true,
+                "--confirm-undo" => args.confirm_undo = true,
+                "--exclude" => args.exclude.push(value_of(&mut raw, "--exclude")?),
+                "--only" => args.include.push(value_of(&mut raw, "--only")?),
+                "--show-gc-hint" => args.show_gc_hint = true,
+                "--local-only" => args.local_only = true,
+                "--skip-head-ancestors" => args.skip_head_ancestors = true,
+                "--count-only" => args.count_only = true,
+                "--list" => args.list = true,
+                "--restore-last" => args.restore_last = true,
+                "--first-parent" => args.first_parent = true,
+                "--mark" => args.mark = true,
+                "--full-ref" => args.full_ref = true,
+                "--verbose" => args.verbose = true,
+                "--bulk-threshold" => {
+                    let threshold = value_of(&mut raw, "--bulk-threshold")?;
+                    args.bulk_threshold = parse_value("--bulk-threshold", &threshold)?;
+                }
+                "--width" => {
+                    let width = value_of(&mut raw, "--width")?;
+                    args.width = Some(parse_value("--width", &width)?);
+                }
+                "--timeout" => {
+                    let timeout = value_of(&mut raw, "--timeout")?;
+                    args.timeout = Some(parse_value("--timeout", &timeout)?);
+                }
+                "--branch" => args.branch = Some(value_of(&mut raw, "--branch")?),
+                "--pre-delete-hook" => {
+                    args.pre_delete_hook = Some(value_of(&mut raw, "--pre-delete-hook")?)
+                }
+                "--lossy-names" => args.lossy_names = true,
+                "--undo-log" => args.undo_log = Some(value_of(&mut raw, "--undo-log")?),
+                "--record" => args.record = Some(value_of(&mut raw, "--record")?),
+                "--replay" => args.replay = Some(value_of(&mut raw, "--replay")?),
+                "--auto-delete-merged" => args.auto_delete_merged = true,
+                "--delete-pattern" => {
+                    args.delete_pattern = Some(value_of(&mut raw, "--delete-pattern")?)
+                }
+                "--hash-len" => {
+                    let hash_len = value_of(&mut raw, "--hash-len")?;
+                    args.hash_len = parse_value("--hash-len", &hash_len)?;
+                }
+                "--limit" => {
+                    let limit = value_of(&mut raw, "--limit")?;
+                    args.limit = Some(parse_value("--limit", &limit)?);
+                }
+                "--actions" => args.actions = Some(value_of(&mut raw, "--actions")?),
+                "--merged" => match raw.next_if(|value| !value.starts_with('-')) {
+                    Some(value) if args.path.is_none() && !is_merged_base(&value) => {
+                        args.merged = Some(None);
+                        args.path = Some(value);
+                    }
+                    base => args.merged = Some(base),
+                },
+                _ if arg.starts_with("--merged=") => {
+                    let base = &arg["--merged=".len()..];
+                    if base.is_empty() {
+                        return Err(Error::MissingValue("--merged"));
+                    }
+                    args.merged = Some(Some(base.to_string()));
+                }
+                "--older-than" => {
+                    let days = value_of(&mut raw, "--older-than")?;
+                    args.older_than = Some(parse_value("--older-than", &days)?);
+                }
+                "--since" => {
+                    let since = value_of(&mut raw, "--since")?;
+                    args.since = Some(parse_date("--since", &since, false)?);
+                }
+                "--until" => {
+                    let until = value_of(&mut raw, "--until")?;
+                    args.until = Some(parse_date("--until", &until, true)?);
+                }
+                "--protect" => args.protected.push(value_of(&mut raw, "--protect")?),
+                "--input-mode" => {
+                    let mode = value_of(&mut raw, "--input-mode")?;
+                    args.input_mode = Some(parse_value("--input-mode", &mode)?);
+                }
+                "--format" => {
+                    let format = value_of(&mut raw, "--format")?;
+                    args.format = Some(parse_value("--format", &format)?);
+                }
+                _ if !arg.starts_with('-') && args.path.is_none() => args.path = Some(arg),
+                _ => return Err(Error::UnknownArgument(arg)),
+            }
+        }
+
+        // The first of `--protect`, `ARBORIST_PROTECT` and the config file that names any
+        // branch wins outright. Only the environment variable adds to the built-in list, so
+        // `ARBORIST_PROTECT=develop` still protects `master` and `main`.
+        let default_protected = DEFAULT_PROTECTED.iter().map(|name| name.to_string());
+        let env_protected: Vec<String> = std::env::var("ARBORIST_PROTECT")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+
+        if args.protected.is_empty() && !env_protected.is_empty() {
+            args.protected = default_protected.chain(env_protected).collect();
+        } else if args.protected.is_empty() {
+            match config.protect {
+                Some(names) if !names.is_empty() => args.protected = names,
+                _ => args.protected = default_protected.collect(),
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+/// Parses `2023-01-31`, `2023-01-31 14:30`, `2023-01-31T14:30:00` and RFC 3339 timestamps,
+/// anything without an offset is taken as UTC. A bare date stands for the start of the day, or
+/// its very end with `end_of_day` so that `--until` includes the whole day.
+fn parse_date(flag: &'static str, value: &str, end_of_day: bool) -> Result<NaiveDateTime> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.naive_utc());
+    }
+
+    let formats = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ];
+    for format in formats.iter() {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(time);
+        }
+    }
+
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) if end_of_day => Ok(date.and_hms(23, 59, 59)),
+        Ok(date) => Ok(date.and_hms(0, 0, 0)),
+        Err(_) => Err(Error::InvalidValue {
+            flag,
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// How answers are read, one key at a time in raw mode or as lines ended with Enter.
+#[derive(Clone, Copy)]
+enum InputMode {
+    Raw,
+    Line,
+}
+
+impl FromStr for InputMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "raw" => Ok(InputMode::Raw),
+            "line" => Ok(InputMode::Line),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether the argument after `--merged` is its base rather than the repository's path, as
+/// `--merged <path>` reads just like `--merged <base>`. Only a directory that doesn't name a
+/// commit in the current repository is taken as the path.
+fn is_merged_base(value: &str) -> bool {
+    if !Path::new(value).is_dir() {
+        return true;
+    }
+
+    Repository::open_from_env().is_ok_and(|repo| resolve_base(&repo, Some(value)).is_ok())
+}
+
+fn value_of(raw: &mut impl Iterator<Item = String>, flag: &'static str) -> Result<String> {
+    raw.next().ok_or(Error::MissingValue(flag))
+}
+
+fn parse_value<T: FromStr>(flag: &'static str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| Error::InvalidValue {
+        flag,
+        value: value.to_string(),
+    })
+}
+
+struct App {
+    stdin: Input,
+    stdout: Stdout,
+    dry_run: bool,
+    color: bool,
+    /// Only deletions are printed, one branch name per line, along with errors and prompts.
+    quiet: bool,
+    force: bool,
+    /// Prints a header whenever the next branch is in a different `Branch::group`.
+    group: bool,
+    /// Branches are picked from the list view instead of one prompt at a time.
+    tui: bool,
+    /// Deleting a branch moves it to the trash instead.
+    trash: bool,
+    /// Deletes one kind of branch all together instead of reviewing them.
+    prune: Option<Prune>,
+    /// How many characters of each commit hash are shown.
+    hash_len: usize,
+    /// Branches merged into this commit are deleted without asking.
+    auto_delete_base: Option<Oid>,
+    /// Every deletion's undo command is appended here as it happens.
+    undo_log: Option<File>,
+    /// A shell command run with the branch name and id before each deletion, which only goes
+    /// ahead when it succeeds.
+    pre_delete_hook: Option<String>,
+    /// What the unique commits shown for each branch are counted against, the `--merged`
+    /// base or HEAD. `None` while HEAD is unborn, the prompts leave the count out then.
+    base_commits: Option<BaseCommits>,
+    /// Long output is shown with this, `None` when paging is turned off.
+    pager: Option<String>,
+    keys: KeyBindings,
+    /// Deleting only marks branches, they're all deleted together at the end.
+    deferred: bool,
+    /// `d` toggles a mark on the branch instead of deleting it, the marked branches are deleted
+    /// together at the end or when the marks are applied.
+    mark: bool,
+    /// The branches marked with `deferred` or `mark`.
+    marked: Vec<String>,
+    /// Prompts also show when the branch split off from this commit, with `--show-created`.
+    created_base: Option<Oid>,
+    /// Prompts say "3 weeks ago" instead of the commit's timestamp.
+    relative_time: bool,
+    /// Answers every y/n confirmation with yes, the safety checks still run and are shown.
+    yes: bool,
+    /// The review was quit before the last branch.
+    quit: bool,
+    /// Deleting all remaining branches at once needs "yes" typed out when there are more than
+    /// this many.
+    bulk_threshold: usize,
+    /// Overrides the terminal's width for laying out lines.
+    width: Option<usize>,
+    /// Prompts name the remote-tracking branch each branch follows.
+    show_upstream: bool,
+    /// Prompts and the `--tui` list show full reference names like `refs/heads/feature`.
+    full_ref: bool,
+    /// Compares branches with `c` along first parents only.
+    first_parent: bool,
+    /// Answers are read as lines ended with Enter because raw mode is off.
+    line_mode: bool,
+    /// Answers are echoed, unless the terminal already shows them as they're typed.
+    echo: bool,
+    /// Every deletion is confirmed after showing the command that undoes it.
+    confirm_undo: bool,
+    /// Unanswered branch prompts keep the branch after this long.
+    timeout: Option<Duration>,
+    /// Reports how much `git gc` could reclaim once the review is done.
+    gc_hint: bool,
+    /// Where every branch deleted this session pointed, also after undoing a deletion.
+    deleted_tips: Vec<Oid>,
+    keeps: KeepFile,
+    protects: ProtectFile,
+    notes: NoteFile,
+    /// Which reviewed branches are stacked on each other, unknown without a base.
+    stacks: Option<Stacks>,
+    /// Every branch deleted this session, for `--restore-last` to bring back.
+    session: SessionFile,
+    last_deleted: Option<DeletedBranch>,
+    /// What was done with the previous branch, for repeating it with `.`.
+    last_action: Option<BranchAction>,
+    summary: Summary,
+    actions: Option<HashMap<String, BranchAction>>,
+}
+
+/// The looks used for output, all of them disappear with `--no-color`.
+#[derive(Clone, Copy)]
+enum Tone {
+    /// Informational messages.
+    Note,
+    /// Something didn't work but the session carries on.
+    Error,
+    /// Needs a decision before anything happens.
+    Warning,
+    /// Local branch names and commit times.
+    Highlight,
+    /// Remote-tracking branch names.
+    Remote,
+    /// Secondary details like commit hashes.
+    Muted,
+    /// Command keys.
+    Key,
+}
+
+impl Tone {
+    fn style(self) -> ContentStyle {
+        let style = ContentStyle::new();
+
+        match self {
+            Tone::Note => style.foreground(Color::Yellow).attribute(Attribute::Dim),
+            Tone::Error => style.foreground(Color::Red),
+            Tone::Warning => style.foreground(Color::Red).attribute(Attribute::Bold),
+            Tone::Highlight => style.foreground(Color::Green),
+            Tone::Remote => style.foreground(Color::Cyan),
+            Tone::Muted => style.attribute(Attribute::Dim),
+            Tone::Key => style.attribute(Attribute::Bold),
+        }
+    }
+}
+
+/// Which branches `--prune-gone` and `--prune-identical` delete together.
+#[derive(Clone, Copy)]
+enum Prune {
+    /// Their upstream was deleted on the remote.
+    Gone,
+    /// They have no commits that aren't on the base.
+    Identical,
+    /// `--trash` moved them to the trash.
+    Trash,
+}
+
+/// What happened to the branches so far this session.
+#[derive(Default)]
+struct Summary {
+    deleted: usize,
+    kept: usize,
+    skipped: usize,
+}
+
+impl App {
+    /// Prints an informational line, unless `--quiet` asked for nothing but deletions.
+    fn note<D: Display + Clone>(&mut self, message: D) -> Result<()> {
+        if !self.quiet {
+            write!(self.stdout, "{}\r\n", self.paint(message, Tone::Note))?;
+        }
+        Ok(())
+    }
+
+    /// Styles `content` with `tone`, or leaves it plain when colors are turned off.
+    fn paint<D: Display + Clone>(&self, content: D, tone: Tone) -> StyledContent<D> {
+        let style = if self.color {
+            tone.style()
+        } else {
+            ContentStyle::new()
+        };
+
+        StyledContent::new(style, content)
+    }
+
+    /// `base` is the `--merged` base or HEAD, `None` while HEAD is unborn.
+    fn new(
+        args: &Args,
+        repo: &Repository,
+        base: Option<Oid>,
+        keeps: KeepFile,
+        protects: ProtectFile,
+        actions: Option<HashMap<String, BranchAction>>,
+        stdin: Input,
+    ) -> Result<App> {
+        let undo_log = match &args.undo_log {
+            Some(path) => Some(open_undo_log(path)?),
+            None => None,
+        };
+
+        Ok(App {
+            stdin,
+            stdout: io::stdout(),
+            dry_run: args.dry_run,
+            // Quiet output is meant for logs and grep, so it stays plain.
+            color: args.color && !args.quiet,
+            quiet: args.quiet,
+            force: args.force,
+            group: args.group,
+            // The list needs raw mode for its keys.
+            tui: args.tui && !args.line_mode(),
+            prune: args.prune,
+            trash: args.trash,
+            hash_len: args.hash_len,
+            auto_delete_base: base.filter(|_| args.auto_delete_merged),
+            undo_log,
+            pre_delete_hook: args.pre_delete_hook.clone(),
+            base_commits: match base {
+                Some(base) => Some(BaseCommits::new(repo, base, args.first_parent)?),
+                None => None,
+            },
+            pager: pager_command(repo),
+            keys: args.keys.clone(),
+            // Scripted runs and the list view already decide everything before deleting.
+            deferred: args.deferred && actions.is_none() && !args.tui,
+            mark: args.mark && actions.is_none() && !args.tui,
+            marked: Vec::new(),
+            created_base: base.filter(|_| args.show_created),
+            relative_time: args.relative_time,
+            yes: args.yes,
+            quit: false,
+            gc_hint: args.show_gc_hint,
+            bulk_threshold: args.bulk_threshold,
+            width: args.width,
+            show_upstream: args.show_upstream,
+            full_ref: args.full_ref,
+            first_parent: args.first_parent,
+            line_mode: args.line_mode(),
+            echo: args.echo(),
+            confirm_undo: args.confirm_undo,
+            timeout: args
+                .timeout
+                .filter(|_| actions.is_none() && !args.tui)
+                // Keys waited on with a timeout bypass the recorded input.
+                .filter(|_| args.record.is_none() && args.replay.is_none())
+                .filter(|_| !args.line_mode())
+                .map(Duration::from_secs),
+            deleted_tips: Vec::new(),
+            keeps,
+            protects,
+            notes: NoteFile::open(repo)?,
+            session: SessionFile::new(repo),
+            stacks: None,
+            last_deleted: None,
+            last_action: None,
+            summary: Summary::default(),
+            actions,
+        })
+    }
+
+    /// The name the branch is shown by, its full reference name with `--full-ref`.
+    fn branch_name(&self, branch: &Branch) -> String {
+        if self.full_ref {
+            branch.refname()
+        } else {
+            branch.name.clone()
+        }
+    }
+
+    /// How many columns lines are laid out for, `--width` or else the terminal's width.
+    fn width(&self) -> usize {
+        if let Some(width) = self.width {
+            return width;
+        }
+
+        match terminal::size() {
+            Ok((columns, _)) if columns > 0 => usize::from(columns),
+            _ => 80,
+        }
+    }
+
+    /// Prints `text` directly when it fits on the screen and shows it in the pager otherwise,
+    /// falling back to printing it when there's no pager or it can't be started.
+    fn page(&mut self, text: &str) -> Result<()> {
+        let rows = match terminal::size() {
+            Ok((_, rows)) if rows > 0 => usize::from(rows),
+            _ => 24,
+        };
+
+        if let Some(pager) = &self.pager {
+            if text.lines().count() >= rows {
+                // The pager expects a normal terminal, raw mode comes back once it's done.
+                if !self.line_mode {
+                    terminal::disable_raw_mode()?;
+                }
+                let paged = run_pager(pager, text);
+                if !self.line_mode {
+                    terminal::enable_raw_mode()?;
+                }
+
+                if paged.is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+
+        for line in text.lines() {
+            write!(self.stdout, "{}\r\n", line)?;
+        }
+        self.stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn parse_args(raw: &[&str]) -> Args {
+        let raw = raw.iter().map(|arg| arg.to_string());
+        Args::parse_from(Config::default(), raw).unwrap()
+    }
+
+    #[test]
+    fn merged_takes_its_base_attached_or_after_it() {
+        let args = parse_args(&["--merged=develop"]);
+        assert_eq!(args.merged, Some(Some("develop".to_string())));
+
+        let args = parse_args(&["--merged", "develop", "--dry-run"]);
+        assert_eq!(args.merged, Some(Some("develop".to_string())));
+        assert_eq!(args.path, None);
+
+        let args = parse_args(&["--merged", "--dry-run"]);
+        assert_eq!(args.merged, Some(None));
+    }
+
+    #[test]
+    fn merged_leaves_a_directory_after_it_as_the_path() {
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+
+        let args = parse_args(&["--merged", &dir]);
+        assert_eq!(args.merged, Some(None));
+        assert_eq!(args.path, Some(dir.clone()));
+
+        let args = parse_args(&[&dir, "--merged", "develop"]);
+        assert_eq!(args.merged, Some(Some("develop".to_string())));
+        assert_eq!(args.path, Some(dir));
+    }
+
+    fn input(bytes: &[u8]) -> impl Iterator<Item = io::Result<u8>> + '_ {
+        bytes.iter().map(|&byte| Ok(byte))
+    }
+
+    #[test]
+    fn read_char_decodes_multibyte_sequences() {
+        let mut bytes = input("é€😀k".as_bytes());
+
+        assert_eq!(read_char(&mut bytes).unwrap().unwrap(), 'é');
+        assert_eq!(read_char(&mut bytes).unwrap().unwrap(), '€');
+        assert_eq!(read_char(&mut bytes).unwrap().unwrap(), '😀');
+        assert_eq!(read_char(&mut bytes).unwrap().unwrap(), 'k');
+        assert!(read_char(&mut bytes).is_none());
+    }
+
+    #[test]
+    fn read_char_replaces_invalid_sequences() {
+        let mut bytes = input(&[0xe2, 0x28, 0xa1]);
+
+        assert_eq!(
+            read_char(&mut bytes).unwrap().unwrap(),
+            char::REPLACEMENT_CHARACTER
+        );
+    }
+
+    #[test]
+    fn read_key_skips_escape_sequences() {
+        // Up, Ctrl-Right, F1 and Alt-x before a plain key.
+        let mut bytes = input(b"\x1b[A\x1b[1;5C\x1bOP\x1bxk");
+
+        assert_eq!(read_key(&mut bytes).unwrap().unwrap(), 'k');
+        assert!(read_key(&mut bytes).is_none());
+    }
+
+    #[test]
+    fn line_mode_recordings_replay_the_same_answers() {
+        let path = std::env::temp_dir().join(format!("arborist-record-{}", std::process::id()));
+        let answers = |input: &mut Input| {
+            let key = read_answer(input, true).unwrap().unwrap();
+            let name = read_answer_line(input, &mut io::sink(), false).unwrap();
+            let confirmed = read_answer(input, true).unwrap().unwrap();
+            (key, name, confirmed)
+        };
+
+        let mut typed = Input::replay(b"keep\nfeature/x\n y\n".to_vec());
+        typed
+            .record_to(File::create(&path).unwrap(), InputMode::Line)
+            .unwrap();
+        let recorded = answers(&mut typed);
+        assert_eq!(recorded, ('k', "feature/x".to_string(), 'y'));
+
+        let recording = session::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(recording.input_mode, Some(InputMode::Line)));
+        let mut replayed = Input::replay(recording.keys);
+        assert_eq!(answers(&mut replayed), recorded);
+        assert!(read_answer(&mut replayed, true).is_none());
+    }
+
+    #[test]
+    fn line_mode_takes_the_first_key_of_each_line() {
+        let mut bytes = input(b"k\n\n  d \r\nq");
+
+        assert_eq!(read_answer(&mut bytes, true).unwrap().unwrap(), 'k');
+        assert_eq!(read_answer(&mut bytes, true).unwrap().unwrap(), 'd');
+        assert_eq!(read_answer(&mut bytes, true).unwrap().unwrap(), 'q');
+        assert!(read_answer(&mut bytes, true).is_none());
+    }
+
+    #[test]
+    fn running_out_of_input_quits() {
+        let keys = KeyBindings::default();
+        let mut bytes = input(b"d");
+
+        assert_eq!(read_prompt_key(&mut bytes, &keys, false).unwrap(), 'd');
+        let c = read_prompt_key(&mut bytes, &keys, false).unwrap();
+        assert_eq!(keys.action(c).unwrap(), BranchAction::Quit);
+        let c = read_prompt_key(&mut bytes, &keys, false).unwrap();
+        assert_eq!(keys.action(c).unwrap(), BranchAction::Quit);
+    }
+
+    #[test]
+    fn relative_time_picks_the_largest_unit() {
+        assert_eq!(relative_time(Duration::seconds(30)), "just now");
+        assert_eq!(relative_time(Duration::minutes(1)), "1 minute ago");
+        assert_eq!(relative_time(Duration::hours(5)), "5 hours ago");
+        assert_eq!(relative_time(Duration::days(3)), "3 days ago");
+        assert_eq!(relative_time(Duration::days(20)), "2 weeks ago");
+        assert_eq!(relative_time(Duration::days(150)), "5 months ago");
+        assert_eq!(relative_time(Duration::days(800)), "2 years ago");
+    }
+
+    #[test]
+    fn relative_time_of_future_commits() {
+        assert_eq!(relative_time(Duration::days(-2)), "just now");
+    }
 }
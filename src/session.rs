@@ -0,0 +1,209 @@
+use crate::InputMode;
+use arborist_git::{Error, Result};
+use std::fs::{self, File};
+use std::io::{self, Bytes, Read, StdinLock, Write};
+
+/// Where the keys typed at prompts come from, either the terminal or a `--replay` recording,
+/// and optionally written to a `--record` file as they're read.
+pub struct Input {
+    source: Source,
+    record: Option<File>,
+    /// The branch whose prompt is showing, written next to every recorded key.
+    pub branch: String,
+}
+
+enum Source {
+    Terminal(Bytes<StdinLock<'static>>),
+    Replay(std::vec::IntoIter<u8>),
+}
+
+/// What `load` read from a recording.
+pub struct Recording {
+    /// The branches up for review when it was recorded, `None` when that line was removed.
+    pub branches: Option<Vec<String>>,
+    /// How answers were read while recording, `None` when that line was removed.
+    pub input_mode: Option<InputMode>,
+    pub keys: Vec<u8>,
+}
+
+/// Stands in for the branch in keys pressed outside of any branch prompt, it can't clash with
+/// a real branch since git doesn't allow names starting with a dash.
+const NO_BRANCH: &str = "-";
+
+const BRANCHES_HEADER: &str = "# branches:";
+
+const INPUT_MODE_HEADER: &str = "# input mode:";
+
+impl Input {
+    pub fn terminal() -> Input {
+        Input {
+            source: Source::Terminal(io::stdin().lock().bytes()),
+            record: None,
+            branch: NO_BRANCH.to_string(),
+        }
+    }
+
+    /// Feeds the recorded keys back instead of reading the terminal, running out of them is
+    /// the same as stdin ending.
+    pub fn replay(keys: Vec<u8>) -> Input {
+        Input {
+            source: Source::Replay(keys.into_iter()),
+            record: None,
+            branch: NO_BRANCH.to_string(),
+        }
+    }
+
+    /// Starts writing every key read from now on to `file`, noting the `input_mode` they're
+    /// read in since line mode sees Enter after every answer and raw mode doesn't.
+    pub fn record_to(&mut self, mut file: File, input_mode: InputMode) -> Result<()> {
+        writeln!(
+            file,
+            "# Recorded by arborist-git, replay it with --replay. Every line is a branch and a key pressed while it was shown."
+        )?;
+        let mode = match input_mode {
+            InputMode::Raw => "raw",
+            InputMode::Line => "line",
+        };
+        writeln!(file, "{} {}", INPUT_MODE_HEADER, mode)?;
+        self.record = Some(file);
+        Ok(())
+    }
+
+    /// Notes the `branches` up for review in the recording, once they're known. Keys answering
+    /// questions asked before that, like which base to compare against, come first.
+    pub fn record_branches(&mut self, branches: &[String]) -> Result<()> {
+        if let Some(record) = &mut self.record {
+            writeln!(record, "{} {}", BRANCHES_HEADER, branches.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Input {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let byte = match &mut self.source {
+            Source::Terminal(bytes) => match bytes.next()? {
+                Ok(byte) => byte,
+                Err(error) => return Some(Err(error)),
+            },
+            Source::Replay(bytes) => bytes.next()?,
+        };
+
+        if let Some(record) = &mut self.record {
+            if let Err(error) = writeln!(record, "{} {}", self.branch, key_name(byte)) {
+                return Some(Err(error));
+            }
+        }
+
+        Some(Ok(byte))
+    }
+}
+
+/// Reads a recording made with `--record`, which may have been edited by hand since.
+pub fn load(path: &str) -> Result<Recording> {
+    let contents = fs::read_to_string(path)?;
+    let mut recording = Recording {
+        branches: None,
+        input_mode: None,
+        keys: Vec::new(),
+    };
+
+    for (index, line) in contents.lines().enumerate() {
+        if let Some(branches) = line.strip_prefix(BRANCHES_HEADER) {
+            recording.branches = Some(branches.split_whitespace().map(String::from).collect());
+            continue;
+        }
+        if let Some(mode) = line.strip_prefix(INPUT_MODE_HEADER) {
+            let mode = mode.trim().parse().map_err(|()| Error::InvalidRecording {
+                line: index + 1,
+                content: line.to_string(),
+            })?;
+            recording.input_mode = Some(mode);
+            continue;
+        }
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let key = line
+            .split_once(' ')
+            .and_then(|(_, key)| parse_key(key.trim()));
+        match key {
+            Some(key) => recording.keys.extend(key),
+            None => {
+                return Err(Error::InvalidRecording {
+                    line: index + 1,
+                    content: line.to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(recording)
+}
+
+/// How a key is written in a recording, printable keys as themselves and everything else by
+/// name or as a hex byte.
+fn key_name(byte: u8) -> String {
+    match byte {
+        b'\r' | b'\n' => "enter".to_string(),
+        b' ' => "space".to_string(),
+        b'\t' => "tab".to_string(),
+        0x1b => "escape".to_string(),
+        0x7f | 0x08 => "backspace".to_string(),
+        0x03 => "ctrl-c".to_string(),
+        0x21..=0x7e => char::from(byte).to_string(),
+        byte => format!("0x{:02x}", byte),
+    }
+}
+
+/// The bytes a key from a recording stands for, any single character works besides the names
+/// `key_name` writes.
+fn parse_key(key: &str) -> Option<Vec<u8>> {
+    let named = match key {
+        // What ends a line in both input modes, raw mode's Enter sends `\r` instead.
+        "enter" => Some(b'\n'),
+        "space" => Some(b' '),
+        "tab" => Some(b'\t'),
+        "escape" => Some(0x1b),
+        "backspace" => Some(0x7f),
+        "ctrl-c" => Some(0x03),
+        _ => None,
+    };
+    if let Some(byte) = named {
+        return Some(vec![byte]);
+    }
+
+    if let Some(hex) = key.strip_prefix("0x").filter(|hex| hex.len() == 2) {
+        return u8::from_str_radix(hex, 16).ok().map(|byte| vec![byte]);
+    }
+
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c.to_string().into_bytes()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_keys_read_back_as_the_same_bytes() {
+        for byte in 0..=u8::MAX {
+            let expected = match byte {
+                b'\r' => b'\n',
+                0x08 => 0x7f,
+                byte => byte,
+            };
+            assert_eq!(parse_key(&key_name(byte)), Some(vec![expected]));
+        }
+        assert_eq!(parse_key("é"), Some("é".as_bytes().to_vec()));
+        assert_eq!(parse_key("dd"), None);
+    }
+}
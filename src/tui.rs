@@ -0,0 +1,160 @@
+use super::{delete_branch, truncate, write_summary, App, Tone};
+use arborist_git::{Branch, Result};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use git2::Repository;
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// Shows every branch in one list to mark for deletion, then deletes the marked ones once the
+/// selection is confirmed with Enter. Quitting with `q` or Esc leaves every branch alone.
+pub fn review_branches(repo: &Repository, branches: &mut [Branch], app: &mut App) -> Result<()> {
+    execute!(app.stdout, EnterAlternateScreen, Hide)?;
+    let marked = select_branches(branches, app);
+    execute!(app.stdout, Show, LeaveAlternateScreen)?;
+
+    let marked = match marked? {
+        Some(marked) => marked,
+        None => {
+            app.quit = true;
+            return Ok(());
+        }
+    };
+
+    for (branch, marked) in branches.iter_mut().zip(marked) {
+        if marked {
+            delete_branch(repo, branch, app)?;
+        } else if branch.is_head || branch.in_worktree {
+            app.summary.skipped += 1;
+        } else {
+            app.summary.kept += 1;
+        }
+    }
+
+    write_summary(repo, app)
+}
+
+/// Runs the list until the user applies or quits, returning which branches were marked or
+/// `None` after quitting.
+fn select_branches(branches: &[Branch], app: &mut App) -> Result<Option<Vec<bool>>> {
+    let mut marked = vec![false; branches.len()];
+    let mut selected = 0;
+    let mut scroll = 0;
+
+    loop {
+        let rows = match terminal::size() {
+            Ok((_, rows)) if rows > 0 => rows,
+            _ => 24,
+        };
+        // The header and footer take one line each.
+        let visible = usize::from(rows).saturating_sub(2).max(1);
+
+        if selected < scroll {
+            scroll = selected;
+        } else if selected >= scroll + visible {
+            scroll = selected + 1 - visible;
+        }
+
+        let width = app.width();
+        draw(branches, &marked, selected, scroll, visible, width, app)?;
+
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+
+        match key {
+            KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+            } => return Ok(None),
+            KeyEvent { code, .. } => match code {
+                KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    selected = (selected + 1).min(branches.len() - 1)
+                }
+                KeyCode::PageUp => selected = selected.saturating_sub(visible),
+                KeyCode::PageDown => selected = (selected + visible).min(branches.len() - 1),
+                KeyCode::Home => selected = 0,
+                KeyCode::End => selected = branches.len() - 1,
+                KeyCode::Char(' ') => {
+                    let branch = &branches[selected];
+                    if !branch.is_head && !branch.in_worktree {
+                        marked[selected] = !marked[selected];
+                    }
+                }
+                KeyCode::Enter => return Ok(Some(marked)),
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                _ => {}
+            },
+        }
+    }
+}
+
+fn draw(
+    branches: &[Branch],
+    marked: &[bool],
+    selected: usize,
+    scroll: usize,
+    visible: usize,
+    width: usize,
+    app: &mut App,
+) -> Result<()> {
+    let count = marked.iter().filter(|&&marked| marked).count();
+
+    queue!(app.stdout, MoveTo(0, 0), Clear(ClearType::All))?;
+
+    let header = format!("{} branches, {} marked for deletion", branches.len(), count);
+    write!(
+        app.stdout,
+        "{}\r\n",
+        app.paint(truncate(&header, width), Tone::Note)
+    )?;
+
+    for (index, branch) in branches.iter().enumerate().skip(scroll).take(visible) {
+        let cursor = if index == selected { '>' } else { ' ' };
+        let mark = if branch.is_head {
+            "(current)"
+        } else if branch.in_worktree {
+            "(worktree)"
+        } else if marked[index] {
+            "[x]"
+        } else {
+            "[ ]"
+        };
+        let line = format!(
+            "{} {} {}  {}  {}",
+            cursor,
+            mark,
+            app.branch_name(branch),
+            branch.time,
+            branch.summary
+        );
+        let line = truncate(&line, width);
+
+        let tone = if index == selected {
+            Tone::Key
+        } else if marked[index] {
+            Tone::Warning
+        } else {
+            Tone::Muted
+        };
+        write!(app.stdout, "{}\r\n", app.paint(line, tone))?;
+    }
+
+    queue!(
+        app.stdout,
+        MoveTo(0, u16::try_from(visible + 1).unwrap_or(u16::MAX))
+    )?;
+    let footer = "\u{2191}/\u{2193} move, space marks for deletion, enter deletes the marked branches, q quits";
+    write!(
+        app.stdout,
+        "{}",
+        app.paint(truncate(footer, width), Tone::Muted)
+    )?;
+
+    app.stdout.flush()?;
+    Ok(())
+}